@@ -0,0 +1,354 @@
+//! Caching of initialization sequences between runs.
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::Path,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{graph::Fnv1a, internal::TypeInitDef, Replayed};
+
+/// Forwards compatible, opaque cache structure. Used to cache initialization sequences.
+/// Caching can be disabled by turning off the default features for this crate.
+///
+/// A cache can be stored with `to_bytes()` and loaded again with `from_bytes()`, or with serde
+/// when the `serde` feature is enabled.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Cache {
+    pub(crate) inner: CacheVersion,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub(crate) enum CacheVersion {
+    /// Indices into the list of uninitialized types. These can't be mapped back to types
+    /// reliably, so caches in this format are discarded.
+    V1(Vec<usize>),
+    /// The full type names of every type, in the order they were initialized, along with a
+    /// fingerprint of the dependency graph they were initialized from.
+    V2 {
+        fingerprint: u64,
+        order: Vec<String>,
+    },
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            inner: CacheVersion::V2 {
+                fingerprint: 0,
+                order: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Identifies the encoding produced by `Cache::to_bytes()`.
+const MAGIC: &[u8; 4] = b"ITRC";
+
+/// The version of the encoding produced by `Cache::to_bytes()`.
+const ENCODING_VERSION: u8 = 1;
+
+/// Why bytes couldn't be decoded into a `Cache`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheDecodeError {
+    /// The bytes weren't produced by `Cache::to_bytes()`.
+    BadMagic,
+    /// The bytes were produced by a newer version of this crate.
+    UnsupportedVersion(u8),
+    /// The bytes end before the cache does.
+    Truncated,
+    /// The bytes don't match their checksum, so they've been corrupted.
+    ChecksumMismatch,
+    /// The bytes passed their checksum, but don't describe a valid cache.
+    Malformed,
+}
+
+impl Display for CacheDecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CacheDecodeError::BadMagic => write!(f, "not an init_tree cache"),
+            CacheDecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported cache encoding version {}", v)
+            }
+            CacheDecodeError::Truncated => write!(f, "cache is truncated"),
+            CacheDecodeError::ChecksumMismatch => write!(f, "cache checksum doesn't match"),
+            CacheDecodeError::Malformed => write!(f, "cache is malformed"),
+        }
+    }
+}
+
+impl Error for CacheDecodeError {}
+
+impl Cache {
+    /// Encodes this cache in a compact, versioned and checksummed binary format, which can be
+    /// loaded again with `from_bytes()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{Cache, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Config>();
+    /// tree.enable_caching(true);
+    /// let bytes = tree.init().take_cache().unwrap().to_bytes();
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Config>();
+    /// tree.load_cache(Cache::from_bytes(&bytes).unwrap());
+    /// assert!(tree.init().cache_was_correct());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(ENCODING_VERSION);
+        match &self.inner {
+            CacheVersion::V1(indices) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+                for i in indices {
+                    bytes.extend_from_slice(&(*i as u64).to_le_bytes());
+                }
+            }
+            CacheVersion::V2 { fingerprint, order } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&fingerprint.to_le_bytes());
+                bytes.extend_from_slice(&(order.len() as u32).to_le_bytes());
+                for name in order {
+                    bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(name.as_bytes());
+                }
+            }
+        }
+        let mut checksum = Fnv1a::default();
+        checksum.write(&bytes);
+        bytes.extend_from_slice(&checksum.finish().to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a cache produced by `to_bytes()`. Corrupt or truncated input produces an error.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CacheDecodeError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(CacheDecodeError::BadMagic);
+        }
+        let mut reader = Reader(&bytes[MAGIC.len()..]);
+        let version = reader.u8()?;
+        if version != ENCODING_VERSION {
+            return Err(CacheDecodeError::UnsupportedVersion(version));
+        }
+        if bytes.len() < MAGIC.len() + 1 + 8 {
+            return Err(CacheDecodeError::Truncated);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        let mut expected = Fnv1a::default();
+        expected.write(body);
+        if expected.finish().to_le_bytes() != checksum {
+            return Err(CacheDecodeError::ChecksumMismatch);
+        }
+        let mut reader = Reader(&body[MAGIC.len() + 1..]);
+        let inner = match reader.u8()? {
+            1 => {
+                let len = reader.u32()? as usize;
+                let mut indices = Vec::with_capacity(len.min(reader.0.len() / 8));
+                for _ in 0..len {
+                    let i = reader.u64()?;
+                    indices.push(i.try_into().unwrap_or(usize::MAX));
+                }
+                CacheVersion::V1(indices)
+            }
+            2 => {
+                let fingerprint = reader.u64()?;
+                let len = reader.u32()? as usize;
+                let mut order = Vec::with_capacity(len.min(reader.0.len() / 4));
+                for _ in 0..len {
+                    let name_len = reader.u32()? as usize;
+                    let name = std::str::from_utf8(reader.take(name_len)?)
+                        .map_err(|_| CacheDecodeError::Malformed)?;
+                    order.push(name.to_owned());
+                }
+                CacheVersion::V2 { fingerprint, order }
+            }
+            _ => return Err(CacheDecodeError::Malformed),
+        };
+        if !reader.0.is_empty() {
+            return Err(CacheDecodeError::Malformed);
+        }
+        Ok(Self { inner })
+    }
+
+    /// Loads a cache written by `write_file()`. Returns `None` if the file is missing, unreadable
+    /// or corrupt.
+    pub(crate) fn read_file(path: &Path) -> Option<Self> {
+        Self::from_bytes(&fs::read(path).ok()?).ok()
+    }
+
+    /// Writes this cache to a file atomically, by writing it to a temporary file next to it and
+    /// renaming that over the original. Readers see either the old cache or the new one, never
+    /// a partially written one.
+    pub(crate) fn write_file(&self, path: &Path) -> io::Result<()> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+        let result = fs::File::create(&temp_path).and_then(|mut file| {
+            io::Write::write_all(&mut file, &self.to_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Creates a cache recording these types, in the order they were initialized.
+    pub(crate) fn new(fingerprint: u64, built: &[TypeInitDef]) -> Self {
+        Self {
+            inner: CacheVersion::V2 {
+                fingerprint,
+                order: built.iter().map(|d| d.type_name.to_owned()).collect(),
+            },
+        }
+    }
+
+    /// Returns the cached initialization order, if this cache was created from a graph with the
+    /// same fingerprint.
+    pub(crate) fn order(&self, fingerprint: u64) -> Result<&[String], CacheRejection> {
+        match &self.inner {
+            CacheVersion::V1(_) => Err(CacheRejection::OutdatedFormat),
+            CacheVersion::V2 { order, .. } if order.is_empty() => Err(CacheRejection::Empty),
+            CacheVersion::V2 {
+                fingerprint: cached,
+                order,
+            } => {
+                if *cached == fingerprint {
+                    Ok(order)
+                } else {
+                    Err(CacheRejection::FingerprintMismatch {
+                        cached: *cached,
+                        current: fingerprint,
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// Why a cache was rejected without being applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheRejection {
+    /// The cache has no entries, such as when caching was enabled without loading a cache.
+    Empty,
+    /// The cache was created by an older version of this crate, in a format that can't be
+    /// applied reliably.
+    OutdatedFormat,
+    /// The cache was created from a different dependency graph, such as one with types added or
+    /// removed.
+    FingerprintMismatch { cached: u64, current: u64 },
+}
+
+impl Display for CacheRejection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CacheRejection::Empty => write!(f, "it has no entries"),
+            CacheRejection::OutdatedFormat => write!(f, "it uses an outdated format"),
+            CacheRejection::FingerprintMismatch { cached, current } => write!(
+                f,
+                "it was created from a different dependency graph ({:016x}, expected {:016x})",
+                cached, current
+            ),
+        }
+    }
+}
+
+/// Describes how a loaded cache was used during initialization, such as to find out why
+/// initialization has become slower. Available from `InitializedTree::cache_report()`, and
+/// formatted as a single line with `Display` for logging.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheReport {
+    /// Why the cache was rejected without applying any of it, if it was.
+    pub rejected: Option<CacheRejection>,
+    /// Cached entries that were initialized in their cached position.
+    pub applied: Vec<String>,
+    /// Cached entries that were skipped because they don't name a type in this tree, or name a
+    /// type that was already initialized.
+    pub skipped: Vec<String>,
+    /// Cached entries that couldn't be initialized in their cached position because their
+    /// dependencies weren't ready yet.
+    pub not_ready: Vec<String>,
+    /// Types that had to be discovered afresh, because the cache didn't provide them.
+    pub discovered: Vec<&'static str>,
+}
+
+impl CacheReport {
+    /// Returns true if the cache was completely correct.
+    pub fn is_correct(&self) -> bool {
+        self.rejected.is_none()
+            && self.skipped.is_empty()
+            && self.not_ready.is_empty()
+            && self.discovered.is_empty()
+    }
+
+    /// Notes what happened to an entry of the cache when it was replayed.
+    pub(crate) fn record(&mut self, name: &str, outcome: Replayed) {
+        let name = name.to_owned();
+        match outcome {
+            Replayed::Applied => self.applied.push(name),
+            Replayed::NotReady => self.not_ready.push(name),
+            Replayed::Skipped => self.skipped.push(name),
+        }
+    }
+}
+
+impl Display for CacheReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(rejected) = &self.rejected {
+            write!(f, "cache rejected because {}", rejected)?;
+        } else {
+            write!(f, "cache applied {} entries", self.applied.len())?;
+            if !self.skipped.is_empty() {
+                write!(f, ", skipped [{}]", self.skipped.join(", "))?;
+            }
+            if !self.not_ready.is_empty() {
+                write!(f, ", not ready [{}]", self.not_ready.join(", "))?;
+            }
+        }
+        if !self.discovered.is_empty() {
+            write!(f, ", discovered [{}]", self.discovered.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads little endian values from the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CacheDecodeError> {
+        if self.0.len() < len {
+            return Err(CacheDecodeError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheDecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheDecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
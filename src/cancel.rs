@@ -0,0 +1,47 @@
+//! Stopping an initialization that's in progress.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Stops an initialization from another thread, such as when the user quits during a long
+/// startup or a supervisor times out. Registered with `InitTree::cancel_with()`. Clones share
+/// the same state, so one clone can be kept to cancel while another is given to the tree.
+///
+/// Cancelling doesn't interrupt a constructor that's already running, but no new ones are
+/// started afterwards.
+///
+/// # Example
+///
+/// ```
+/// # use init_tree::{CancellationToken, InitError, InitTree};
+/// #[derive(Default)]
+/// struct Config;
+///
+/// let token = CancellationToken::new();
+/// let mut tree = InitTree::new();
+/// tree.add::<Config>();
+/// tree.cancel_with(token.clone());
+/// token.cancel();
+/// assert!(matches!(tree.try_init(), Err(InitError::Cancelled { .. })));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Asks every initialization using this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if `cancel()` was called on this token or one of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,281 @@
+//! Drawing the dependency graph of a tree as Graphviz DOT or Mermaid.
+
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    time::Duration,
+};
+
+use crate::{
+    graph::Graph,
+    internal::{DependencyKind, TypeInitDef},
+    Constraints, NodeTiming,
+};
+
+/// What to highlight when exporting a dependency graph with `to_dot()` or `to_mermaid()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// Highlight the types nothing else in the tree depends on.
+    pub roots: bool,
+    /// Highlight the types that wait on each other, and so can never be initialized.
+    pub cycles: bool,
+    /// Highlight the longest chain of types that have to be initialized one after another. When
+    /// timings were recorded the chain taking the most time is highlighted, otherwise the one
+    /// with the most types.
+    pub critical_path: bool,
+    /// The names of types to highlight as having failed, such as those from
+    /// `InitError::nodes()`.
+    pub failed: Vec<String>,
+}
+
+/// A single type to draw.
+struct Node {
+    label: String,
+    root: bool,
+    cycle: bool,
+    failed: bool,
+    critical: bool,
+}
+
+/// A dependency from one type on another, by their index in the sorted nodes.
+struct Edge {
+    from: usize,
+    to: usize,
+    kind: DependencyKind,
+    critical: bool,
+}
+
+/// The nodes and edges to draw, with everything to highlight worked out.
+pub(crate) struct Diagram {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Diagram {
+    /// Lays out these types, with the time each one took to initialize if it was recorded.
+    pub(crate) fn new(
+        defs: &[TypeInitDef],
+        constraints: &Constraints,
+        timings: Option<&HashMap<TypeId, NodeTiming>>,
+        options: &ExportOptions,
+    ) -> Self {
+        let mut defs = defs.iter().collect::<Vec<_>>();
+        defs.sort_by_key(|d| d.type_name);
+        let defs = defs.into_iter().copied().collect::<Vec<_>>();
+        let index = defs
+            .iter()
+            .enumerate()
+            .map(|(i, d)| ((d.id)(), i))
+            .collect::<HashMap<_, _>>();
+        let mut edges = Vec::new();
+        let mut depended_on = HashSet::new();
+        for (from, def) in defs.iter().enumerate() {
+            for dep in (def.deps)() {
+                if let Some(&to) = index.get(&((dep.def)().id)()) {
+                    depended_on.insert(to);
+                    edges.push(Edge {
+                        from,
+                        to,
+                        kind: dep.kind,
+                        critical: false,
+                    });
+                }
+            }
+        }
+        for (before, after) in &constraints.order {
+            if let (Some(&from), Some(&to)) = (index.get(after), index.get(before)) {
+                depended_on.insert(to);
+                edges.push(Edge {
+                    from,
+                    to,
+                    kind: DependencyKind::After,
+                    critical: false,
+                });
+            }
+        }
+        edges.sort_by_key(|e| (e.from, e.to));
+        edges.dedup_by_key(|e| (e.from, e.to));
+
+        let graph = Graph::new(&defs, constraints);
+        let cycles = if options.cycles {
+            graph.cycles().into_iter().flatten().collect()
+        } else {
+            HashSet::new()
+        };
+        let critical = if options.critical_path {
+            let weights = defs
+                .iter()
+                .map(|d| {
+                    timings
+                        .and_then(|t| t.get(&(d.id)()))
+                        .map_or(1, |t| t.elapsed.as_nanos().max(1))
+                })
+                .collect::<Vec<_>>();
+            graph.critical_path(&weights)
+        } else {
+            Vec::new()
+        };
+        for pair in critical.windows(2) {
+            // The path runs from dependencies to dependents, while edges point the other way.
+            for edge in edges
+                .iter_mut()
+                .filter(|e| e.from == pair[1] && e.to == pair[0])
+            {
+                edge.critical = true;
+            }
+        }
+        let critical = critical.into_iter().collect::<HashSet<_>>();
+
+        let nodes = defs
+            .iter()
+            .enumerate()
+            .map(|(i, def)| {
+                let mut label = def.name.to_owned();
+                if let Some(elapsed) = timings.and_then(|t| t.get(&(def.id)())) {
+                    label.push('\n');
+                    label.push_str(&format_duration(elapsed.elapsed));
+                }
+                Node {
+                    label,
+                    root: options.roots && !depended_on.contains(&i),
+                    cycle: cycles.contains(&i),
+                    failed: options
+                        .failed
+                        .iter()
+                        .any(|f| f == def.name || f == def.type_name),
+                    critical: critical.contains(&i),
+                }
+            })
+            .collect();
+        Self { nodes, edges }
+    }
+
+    /// Writes the diagram in Graphviz DOT. Edges point from a type to its dependencies.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph init_tree {\n    node [shape=box];\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node
+                .label
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            let mut attributes = vec![format!("label=\"{}\"", label)];
+            let mut styles = Vec::new();
+            if node.root {
+                styles.push("bold");
+            }
+            if node.failed {
+                styles.push("filled");
+                attributes.push("fillcolor=\"#f4cccc\"".to_owned());
+            }
+            if node.cycle {
+                attributes.push("color=\"#cc0000\"".to_owned());
+            } else if node.critical {
+                attributes.push("color=\"#0066cc\"".to_owned());
+                attributes.push("penwidth=2".to_owned());
+            }
+            if !styles.is_empty() {
+                attributes.push(format!("style=\"{}\"", styles.join(",")));
+            }
+            let _ = writeln!(dot, "    n{} [{}];", i, attributes.join(", "));
+        }
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            match edge.kind {
+                DependencyKind::Borrow => {}
+                DependencyKind::Factory => attributes.push("style=dashed"),
+                DependencyKind::Late => attributes.push("style=dotted"),
+                DependencyKind::Consume => attributes.push("style=bold"),
+                DependencyKind::After => attributes.extend(["style=dashed", "arrowhead=empty"]),
+            }
+            if edge.critical {
+                attributes.push("color=\"#0066cc\"");
+                attributes.push("penwidth=2");
+            }
+            if attributes.is_empty() {
+                let _ = writeln!(dot, "    n{} -> n{};", edge.from, edge.to);
+            } else {
+                let _ = writeln!(
+                    dot,
+                    "    n{} -> n{} [{}];",
+                    edge.from,
+                    edge.to,
+                    attributes.join(", ")
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Writes the diagram as a Mermaid flowchart. Edges point from a type to its dependencies.
+    pub(crate) fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart TD\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node
+                .label
+                .replace('"', "#quot;")
+                .replace('<', "#lt;")
+                .replace('>', "#gt;")
+                .replace('\n', "<br/>");
+            let _ = writeln!(mermaid, "    n{}[\"{}\"]", i, label);
+        }
+        let mut critical_links = Vec::new();
+        for (link, edge) in self.edges.iter().enumerate() {
+            let arrow = match edge.kind {
+                DependencyKind::Borrow => "-->",
+                DependencyKind::Factory | DependencyKind::Late => "-.->",
+                DependencyKind::Consume => "==>",
+                DependencyKind::After => "--o",
+            };
+            let _ = writeln!(mermaid, "    n{} {} n{}", edge.from, arrow, edge.to);
+            if edge.critical {
+                critical_links.push(link.to_string());
+            }
+        }
+        let classes = [
+            ("root", "stroke-width:3px", self.select(|n| n.root)),
+            ("failed", "fill:#f4cccc", self.select(|n| n.failed)),
+            ("cycle", "stroke:#cc0000", self.select(|n| n.cycle)),
+            (
+                "critical",
+                "stroke:#0066cc,stroke-width:3px",
+                self.select(|n| n.critical && !n.cycle),
+            ),
+        ];
+        for (class, style, nodes) in classes {
+            if !nodes.is_empty() {
+                let _ = writeln!(mermaid, "    classDef {} {}", class, style);
+                let _ = writeln!(mermaid, "    class {} {}", nodes.join(","), class);
+            }
+        }
+        if !critical_links.is_empty() {
+            let _ = writeln!(
+                mermaid,
+                "    linkStyle {} stroke:#0066cc,stroke-width:3px",
+                critical_links.join(",")
+            );
+        }
+        mermaid
+    }
+
+    /// Returns the Mermaid ids of the nodes matching `filter`.
+    fn select(&self, filter: impl Fn(&Node) -> bool) -> Vec<String> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| filter(n))
+            .map(|(i, _)| format!("n{}", i))
+            .collect()
+    }
+}
+
+/// Formats a duration in milliseconds, or microseconds if it's shorter than one.
+fn format_duration(duration: Duration) -> String {
+    if duration >= Duration::from_millis(1) {
+        format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{} µs", duration.as_micros())
+    }
+}
@@ -0,0 +1,372 @@
+//! Analysis of the dependency graph of a tree, without initializing anything.
+
+use std::{
+    any::TypeId,
+    collections::{hash_map::Entry, BTreeSet, HashMap, VecDeque},
+};
+
+use crate::{
+    internal::{DependencyKind, TypeInitDef},
+    Constraints,
+};
+
+/// Finds the shortest chain of types from one nothing else depends on down to `target`, each
+/// depending on the next. This explains why `target` was initialized.
+pub(crate) fn dependent_chain<'a>(
+    target: &TypeInitDef,
+    defs: impl Iterator<Item = &'a TypeInitDef>,
+) -> Vec<&'static str> {
+    let mut dependents = HashMap::<TypeId, Vec<&TypeInitDef>>::new();
+    for def in defs {
+        for dep in (def.deps)() {
+            let dependents = dependents.entry(((dep.def)().id)()).or_default();
+            if !dependents.iter().any(|d| (d.id)() == (def.id)()) {
+                dependents.push(def);
+            }
+        }
+    }
+    // Searches up from `target`, remembering the type each one was reached from.
+    let mut reached_from = HashMap::<TypeId, Option<&TypeInitDef>>::new();
+    reached_from.insert((target.id)(), None);
+    let mut queue = VecDeque::from(vec![target]);
+    while let Some(def) = queue.pop_front() {
+        let id = (def.id)();
+        let next = dependents.get(&id).map_or(&[][..], Vec::as_slice);
+        if next.is_empty() {
+            let mut chain = vec![def.name];
+            let mut current = id;
+            while let Some(Some(below)) = reached_from.get(&current) {
+                chain.push(below.name);
+                current = (below.id)();
+            }
+            return chain;
+        }
+        for dependent in next {
+            if let Entry::Vacant(entry) = reached_from.entry((dependent.id)()) {
+                entry.insert(Some(def));
+                queue.push_back(dependent);
+            }
+        }
+    }
+    // Every dependent is part of a cycle.
+    vec![target.name]
+}
+
+/// The types in a tree, along with the types each of them has to wait for.
+pub(crate) struct Graph<'a> {
+    pub(crate) nodes: &'a [TypeInitDef],
+    /// For each node, the indices of the nodes that must be initialized before it.
+    pub(crate) predecessors: Vec<Vec<usize>>,
+}
+
+impl<'a> Graph<'a> {
+    /// Builds the graph of these types. Dependencies on types that aren't in `nodes`, such as
+    /// those provided by a parent scope, are left out.
+    pub(crate) fn new(nodes: &'a [TypeInitDef], constraints: &Constraints) -> Self {
+        let index = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, d)| ((d.id)(), i))
+            .collect::<HashMap<TypeId, usize>>();
+        let predecessors = nodes
+            .iter()
+            .map(|def| {
+                let mut predecessors = (def.deps)()
+                    .iter()
+                    .filter(|d| d.is_ordering())
+                    .map(|d| ((d.def)().id)())
+                    .chain(
+                        constraints
+                            .after
+                            .get(&(def.id)())
+                            .into_iter()
+                            .flatten()
+                            .copied(),
+                    )
+                    .filter_map(|id| index.get(&id).copied())
+                    .collect::<Vec<_>>();
+                predecessors.sort_unstable();
+                predecessors.dedup();
+                predecessors
+            })
+            .collect();
+        Self {
+            nodes,
+            predecessors,
+        }
+    }
+
+    /// Works out the phase each node is initialized in, given the phases some of them were put
+    /// in. A node without a phase of its own goes in the earliest phase of the nodes waiting for
+    /// it, or the last phase, `u32::MAX`, if nothing waits for it.
+    pub(crate) fn phases(&self, explicit: &[Option<u32>]) -> Vec<u32> {
+        let mut phases = explicit
+            .iter()
+            .map(|p| p.unwrap_or(u32::MAX))
+            .collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (node, predecessors) in self.predecessors.iter().enumerate() {
+                for &p in predecessors {
+                    if explicit[p].is_none() && phases[p] > phases[node] {
+                        phases[p] = phases[node];
+                        changed = true;
+                    }
+                }
+            }
+        }
+        phases
+    }
+
+    /// Assigns every node a level, one more than the highest level of the nodes it has to wait
+    /// for. Nodes on the same level don't depend on each other. Returns the indices of the nodes
+    /// that can never be initialized if there are any.
+    pub(crate) fn levels(&self) -> Result<Vec<usize>, Vec<usize>> {
+        let mut successors = vec![Vec::new(); self.nodes.len()];
+        let mut waiting_on = Vec::with_capacity(self.nodes.len());
+        for (i, predecessors) in self.predecessors.iter().enumerate() {
+            for p in predecessors {
+                successors[*p].push(i);
+            }
+            waiting_on.push(predecessors.len());
+        }
+        let mut levels = vec![0; self.nodes.len()];
+        let mut ready = (0..self.nodes.len())
+            .filter(|i| waiting_on[*i] == 0)
+            .collect::<Vec<_>>();
+        let mut resolved = 0;
+        while let Some(i) = ready.pop() {
+            resolved += 1;
+            for s in &successors[i] {
+                levels[*s] = levels[*s].max(levels[i] + 1);
+                waiting_on[*s] -= 1;
+                if waiting_on[*s] == 0 {
+                    ready.push(*s);
+                }
+            }
+        }
+        if resolved == self.nodes.len() {
+            Ok(levels)
+        } else {
+            Err((0..self.nodes.len())
+                .filter(|i| waiting_on[*i] > 0)
+                .collect())
+        }
+    }
+
+    /// Finds the chain of nodes, each waiting on the one before it, with the largest total weight.
+    /// Returns the chain from its first node to its last, or nothing if the graph has cycles.
+    pub(crate) fn critical_path(&self, weights: &[u128]) -> Vec<usize> {
+        let levels = match self.levels() {
+            Ok(levels) => levels,
+            Err(_) => return Vec::new(),
+        };
+        let mut order = (0..self.nodes.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| levels[*i]);
+        // The heaviest chain ending at each node, and the node before it in that chain.
+        let mut total = vec![0; self.nodes.len()];
+        let mut previous = vec![None; self.nodes.len()];
+        for &i in &order {
+            let heaviest = self.predecessors[i].iter().max_by_key(|p| total[**p]);
+            total[i] = weights[i] + heaviest.map_or(0, |p| total[*p]);
+            previous[i] = heaviest.copied();
+        }
+        let mut path = Vec::new();
+        let mut next = (0..self.nodes.len()).max_by_key(|i| total[*i]);
+        while let Some(i) = next {
+            path.push(i);
+            next = previous[i];
+        }
+        path.reverse();
+        path
+    }
+
+    /// Finds the groups of nodes that wait on each other, directly or indirectly. Each group is
+    /// sorted, and the groups are sorted by their first node.
+    pub(crate) fn cycles(&self) -> Vec<Vec<usize>> {
+        // Tarjan's strongly connected components algorithm, with an explicit stack so deep
+        // graphs can't overflow the call stack.
+        let len = self.nodes.len();
+        let mut index = vec![usize::MAX; len];
+        let mut low_link = vec![0; len];
+        let mut on_stack = vec![false; len];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut cycles = Vec::new();
+        for root in 0..len {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            let mut call_stack = vec![(root, 0)];
+            while let Some((node, edge)) = call_stack.pop() {
+                if edge == 0 {
+                    index[node] = next_index;
+                    low_link[node] = next_index;
+                    next_index += 1;
+                    stack.push(node);
+                    on_stack[node] = true;
+                }
+                if let Some(&next) = self.predecessors[node].get(edge) {
+                    call_stack.push((node, edge + 1));
+                    if index[next] == usize::MAX {
+                        call_stack.push((next, 0));
+                    } else if on_stack[next] {
+                        low_link[node] = low_link[node].min(index[next]);
+                    }
+                    continue;
+                }
+                if let Some(&(caller, _)) = call_stack.last() {
+                    low_link[caller] = low_link[caller].min(low_link[node]);
+                }
+                if low_link[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 || self.predecessors[node].contains(&node) {
+                        component.sort_unstable();
+                        cycles.push(component);
+                    }
+                }
+            }
+        }
+        cycles.sort_unstable();
+        cycles
+    }
+}
+
+/// The nodes of a graph that have nothing left to wait for, taken in the order they were
+/// registered in.
+pub(crate) struct ReadyQueue {
+    successors: Vec<Vec<usize>>,
+    /// For each node, how many of its predecessors haven't been initialized yet.
+    waiting_on: Vec<usize>,
+    ready: BTreeSet<usize>,
+}
+
+impl ReadyQueue {
+    pub(crate) fn new(graph: &Graph) -> Self {
+        let mut successors = vec![Vec::new(); graph.nodes.len()];
+        let mut waiting_on = Vec::with_capacity(graph.nodes.len());
+        for (i, predecessors) in graph.predecessors.iter().enumerate() {
+            for p in predecessors {
+                successors[*p].push(i);
+            }
+            waiting_on.push(predecessors.len());
+        }
+        let ready = (0..graph.nodes.len())
+            .filter(|i| waiting_on[*i] == 0)
+            .collect();
+        Self {
+            successors,
+            waiting_on,
+            ready,
+        }
+    }
+
+    /// Takes the first ready node.
+    pub(crate) fn pop(&mut self) -> Option<usize> {
+        self.ready.pop_first()
+    }
+
+    /// Notes that a node was initialized, readying the nodes that were only waiting on it.
+    pub(crate) fn complete(&mut self, node: usize) {
+        for &s in &self.successors[node] {
+            self.waiting_on[s] -= 1;
+            if self.waiting_on[s] == 0 {
+                self.ready.insert(s);
+            }
+        }
+    }
+}
+
+/// Computes a fingerprint of these types, their dependencies and the ordering constraints
+/// between them. The fingerprint doesn't depend on the order the types are listed in, and is
+/// stable across builds and platforms.
+///
+/// Explicit orderings are read from `constraints.after`, so the tree must have been scheduled.
+pub(crate) fn fingerprint(defs: &[TypeInitDef], constraints: &Constraints) -> u64 {
+    let mut nodes = defs
+        .iter()
+        .map(|def| {
+            let mut deps = (def.deps)()
+                .iter()
+                .map(|d| (kind_tag(d.kind), (d.def)().type_name))
+                .collect::<Vec<_>>();
+            deps.sort_unstable();
+            (def.type_name, deps)
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_unstable();
+    nodes.dedup();
+    let mut hash = Fnv1a::default();
+    for (name, deps) in nodes {
+        hash.write(name.as_bytes());
+        hash.write(&[0]);
+        for (kind, dep) in deps {
+            hash.write(&[kind]);
+            hash.write(dep.as_bytes());
+            hash.write(&[0]);
+        }
+        hash.write(&[0xff]);
+    }
+    let names = defs
+        .iter()
+        .map(|d| ((d.id)(), d.type_name))
+        .collect::<HashMap<_, _>>();
+    let mut edges = Vec::new();
+    for def in defs {
+        for before in constraints.after.get(&(def.id)()).into_iter().flatten() {
+            if let Some(before) = names.get(before) {
+                edges.push((def.type_name, *before));
+            }
+        }
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    for (after, before) in edges {
+        hash.write(&[0xfe]);
+        hash.write(after.as_bytes());
+        hash.write(&[0]);
+        hash.write(before.as_bytes());
+        hash.write(&[0]);
+    }
+    hash.finish()
+}
+
+fn kind_tag(kind: DependencyKind) -> u8 {
+    match kind {
+        DependencyKind::Borrow => 1,
+        DependencyKind::Factory => 2,
+        DependencyKind::Late => 3,
+        DependencyKind::Consume => 4,
+        DependencyKind::After => 5,
+    }
+}
+
+/// The 64 bit FNV-1a hash. Unlike `DefaultHasher` its output is guaranteed not to change.
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
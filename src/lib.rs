@@ -5,45 +5,368 @@
 
 use std::{
     any::{Any, TypeId},
-    cell::RefCell,
-    collections::HashMap,
+    cell::{OnceCell, RefMut},
+    collections::{hash_map, HashMap, HashSet},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 #[cfg(feature = "cache")]
-use std::mem::swap;
+use std::path::PathBuf;
 
-#[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
-
-/// Forwards compatible, serde compatible, opaque cache structure. Used to cache initialization sequences.
-/// Caching can be disabled by turning off the default features for this crate.
 #[cfg(feature = "cache")]
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Cache {
-    inner: CacheVersion,
-}
+mod cache;
+mod cancel;
+mod export;
+mod graph;
+mod manifest;
+mod observer;
+mod plan;
+mod timing;
+#[cfg(feature = "tracing")]
+mod trace;
 
 #[cfg(feature = "cache")]
-#[derive(Clone, Debug, Deserialize, Serialize)]
-enum CacheVersion {
-    V1(Vec<usize>),
+pub use cache::{Cache, CacheDecodeError, CacheRejection, CacheReport};
+pub use cancel::CancellationToken;
+pub use export::ExportOptions;
+pub use manifest::{EdgeKind, GraphManifest, Lifetime, ManifestDiff, ManifestEdge, ManifestNode};
+pub use observer::InitObserver;
+pub use plan::{InitPlan, PlanStep};
+pub use timing::{CriticalPath, NodeTiming};
+
+/// Identifies a single type within a tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeInfo {
+    pub id: TypeId,
+    pub name: &'static str,
 }
 
-#[cfg(feature = "cache")]
-impl Default for Cache {
-    fn default() -> Self {
+impl From<&internal::TypeInitDef> for NodeInfo {
+    fn from(def: &internal::TypeInitDef) -> Self {
         Self {
-            inner: CacheVersion::V1(Vec::new()),
+            id: (def.id)(),
+            name: def.name,
+        }
+    }
+}
+
+/// The reasons initializing a tree can fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitError {
+    /// These types could never have all of their dependencies satisfied, usually because of a
+    /// circular dependency or a dependency that was taken from the tree.
+    Unresolved { locked: Vec<&'static str> },
+    /// `node` requested a `Late` handle to `dependency`, but no instance of `dependency` was
+    /// available once everything else had been initialized.
+    UnresolvedLate {
+        node: &'static str,
+        dependency: &'static str,
+    },
+    /// More than one type takes `dependency` by value. A value can only be moved into one
+    /// consumer.
+    MultipleConsumers {
+        dependency: &'static str,
+        consumers: Vec<&'static str>,
+    },
+    /// The plan given to `InitTree::init_with_plan()` was computed from a different dependency
+    /// graph.
+    PlanMismatch { planned: u64, current: u64 },
+    /// `node` depends on `dependency`, but nothing in the tree provides it.
+    MissingProvider {
+        node: &'static str,
+        dependency: &'static str,
+    },
+    /// These types wait on each other, directly or indirectly, so none of them can be
+    /// initialized.
+    Cycle { nodes: Vec<&'static str> },
+    /// Several different types are registered under the same full name, so caches and plans
+    /// can't tell them apart.
+    DuplicateBinding { type_name: &'static str },
+    /// `node` is provided in two different ways, `first` and `second`, each describing the name
+    /// and dependencies of one provider. Registering the same provider more than once is fine.
+    DuplicateProvider {
+        node: &'static str,
+        first: String,
+        second: String,
+    },
+    /// `node` is initialized in `phase`, but has to wait for `dependency`, which is put in the
+    /// later `dependency_phase`.
+    PhaseOrder {
+        node: &'static str,
+        phase: u32,
+        dependency: &'static str,
+        dependency_phase: u32,
+    },
+    /// `node` takes `dependency` by value in a child scope, but `dependency` is held by a parent
+    /// scope, which can only lend it.
+    ConsumedFromParent {
+        node: &'static str,
+        dependency: &'static str,
+    },
+    /// `node` needs `dependency` after it has been taken by value: `node` is made by a `Factory`
+    /// and consumes `dependency` on every `make()`, is made by a `Factory` and borrows a
+    /// `dependency` another type consumes, or holds a `Late` handle to a consumed `dependency`.
+    ConsumedWhileNeeded {
+        node: &'static str,
+        dependency: &'static str,
+    },
+    /// `node` borrows or consumes `task`, which is registered with `InitTree::add_task()` and so
+    /// is dropped as soon as it has run. Other types can only wait for a task with `after()`.
+    TaskDependency {
+        node: &'static str,
+        task: &'static str,
+    },
+    /// `node` borrows or consumes `dependency` more than once, either directly or through a
+    /// `Factory` whose products borrow or consume it.
+    AliasedBorrow {
+        node: &'static str,
+        dependency: &'static str,
+    },
+    /// Initializing `node` panicked with `message`. `chain` lists the types that led to `node`
+    /// being initialized, each depending on the next, from one nothing else depends on down to
+    /// `node` itself. Only returned if panics are caught with `InitTree::catch_panics()`.
+    Panicked {
+        node: &'static str,
+        message: String,
+        chain: Vec<&'static str>,
+    },
+    /// Initialization was stopped with a `CancellationToken`. The types in `built` were
+    /// initialized before then, and any still held by the tree have been dropped again in
+    /// reverse order. Tasks among them were already dropped once they had run, and transient
+    /// types are left out, as only their dependents construct them. The types in `unbuilt` were
+    /// never initialized.
+    Cancelled {
+        built: Vec<&'static str>,
+        unbuilt: Vec<&'static str>,
+    },
+}
+
+impl Display for InitError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            InitError::Unresolved { locked } => write!(
+                f,
+                "Unable to resolve initialization tree. Locked on [{}]",
+                locked.join(", ")
+            ),
+            InitError::UnresolvedLate { node, dependency } => write!(
+                f,
+                "Unable to resolve Late<{}> requested by {}, no instance of it was initialized",
+                dependency, node
+            ),
+            InitError::MultipleConsumers {
+                dependency,
+                consumers,
+            } => write!(
+                f,
+                "{} can only be consumed by one type, but is consumed by [{}]",
+                dependency,
+                consumers.join(", ")
+            ),
+            InitError::PlanMismatch { planned, current } => write!(
+                f,
+                "Plan was computed from a different dependency graph ({:016x}, expected {:016x})",
+                planned, current
+            ),
+            InitError::MissingProvider { node, dependency } => write!(
+                f,
+                "{} depends on {}, but nothing provides it",
+                node, dependency
+            ),
+            InitError::Cycle { nodes } => {
+                write!(f, "Circular dependency between [{}]", nodes.join(", "))
+            }
+            InitError::DuplicateBinding { type_name } => {
+                write!(f, "Several different types are registered as {}", type_name)
+            }
+            InitError::DuplicateProvider {
+                node,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} has two different providers: {} and {}",
+                node, first, second
+            ),
+            InitError::PhaseOrder {
+                node,
+                phase,
+                dependency,
+                dependency_phase,
+            } => write!(
+                f,
+                "{} is initialized in phase {}, but waits for {} in the later phase {}",
+                node, phase, dependency, dependency_phase
+            ),
+            InitError::ConsumedFromParent { node, dependency } => write!(
+                f,
+                "{} takes {} by value, but it's held by a parent scope",
+                node, dependency
+            ),
+            InitError::ConsumedWhileNeeded { node, dependency } => write!(
+                f,
+                "{} needs {} after it has been taken by value",
+                node, dependency
+            ),
+            InitError::TaskDependency { node, task } => write!(
+                f,
+                "{} borrows or consumes {}, which is a task and is dropped once it has run",
+                node, task
+            ),
+            InitError::AliasedBorrow { node, dependency } => write!(
+                f,
+                "{} takes {} more than once while also borrowing or consuming it",
+                node, dependency
+            ),
+            InitError::Panicked {
+                node,
+                message,
+                chain,
+            } => {
+                write!(f, "{} panicked during initialization: {}", node, message)?;
+                if !chain.is_empty() {
+                    write!(f, " (required by {})", chain.join(" -> "))?;
+                }
+                Ok(())
+            }
+            InitError::Cancelled { built, unbuilt } => write!(
+                f,
+                "Initialization was cancelled after building [{}], leaving [{}] unbuilt",
+                built.join(", "),
+                unbuilt.join(", ")
+            ),
+        }
+    }
+}
+
+impl InitError {
+    /// Returns the names of the types involved in this error, such as to highlight them with
+    /// `ExportOptions::failed`.
+    pub fn nodes(&self) -> Vec<&'static str> {
+        match self {
+            InitError::Unresolved { locked } => locked.clone(),
+            InitError::UnresolvedLate { node, dependency }
+            | InitError::MissingProvider { node, dependency }
+            | InitError::AliasedBorrow { node, dependency }
+            | InitError::ConsumedFromParent { node, dependency }
+            | InitError::ConsumedWhileNeeded { node, dependency }
+            | InitError::TaskDependency {
+                node,
+                task: dependency,
+            }
+            | InitError::PhaseOrder {
+                node, dependency, ..
+            } => vec![node, dependency],
+            InitError::MultipleConsumers {
+                dependency,
+                consumers,
+            } => std::iter::once(*dependency)
+                .chain(consumers.iter().copied())
+                .collect(),
+            InitError::PlanMismatch { .. } => Vec::new(),
+            InitError::Cycle { nodes } => nodes.clone(),
+            InitError::DuplicateBinding { type_name } => vec![type_name],
+            InitError::DuplicateProvider { node, .. } => vec![node],
+            InitError::Panicked { node, .. } => vec![node],
+            InitError::Cancelled { unbuilt, .. } => unbuilt.clone(),
+        }
+    }
+
+    /// Fills in the chain of types that led to `def` being initialized if it panicked, searching
+    /// through `defs` for its dependents.
+    fn with_chain<'a>(
+        self,
+        def: &internal::TypeInitDef,
+        defs: impl Iterator<Item = &'a internal::TypeInitDef>,
+    ) -> Self {
+        match self {
+            InitError::Panicked { node, message, .. } => InitError::Panicked {
+                node,
+                message,
+                chain: graph::dependent_chain(def, defs),
+            },
+            error => error,
         }
     }
 }
 
+impl Error for InitError {}
+
+/// Scheduling rules that aren't expressed by a type's own dependencies.
+#[derive(Default, Clone)]
+struct Constraints {
+    /// Types that are initialized once and stored, rather than constructed by a `Factory`.
+    singletons: HashSet<TypeId>,
+    /// Types that are initialized once for their side effects, then dropped.
+    tasks: HashSet<TypeId>,
+    /// Types that must also be ready before a type is initialized.
+    after: HashMap<TypeId, Vec<TypeId>>,
+    /// Orderings added with `InitTree::order()`, as pairs of the type to initialize first and
+    /// the type to initialize after it.
+    order: Vec<(TypeId, TypeId)>,
+}
+
+impl Constraints {
+    /// Returns true if everything this type must wait for is ready.
+    fn ready(&self, def: &internal::TypeInitDef, initialized: &internal::Instances) -> bool {
+        internal::deps_ready(def, initialized)
+            && self
+                .after
+                .get(&(def.id)())
+                .into_iter()
+                .flatten()
+                .all(|id| initialized.is_ready(id))
+    }
+}
+
+/// How constructors are run and watched.
+#[derive(Default, Clone)]
+struct Supervision {
+    observers: Vec<Arc<dyn InitObserver>>,
+    /// Whether to catch panics in constructors, returning them as errors.
+    catch_panics: bool,
+    cancel: Option<CancellationToken>,
+}
+
+impl Supervision {
+    /// Returns true if initialization was cancelled with the token, if there is one.
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+/// What happened to a single type when following a given order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Replayed {
+    Applied,
+    NotReady,
+    Skipped,
+}
+
 /// A tree of types to initialize.
 #[derive(Default, Clone)]
 pub struct InitTree {
     uninitialized: Vec<internal::TypeInitDef>,
+    constraints: Constraints,
+    built: Vec<internal::TypeInitDef>,
+    timings: Option<timing::Timings>,
+    tags: HashMap<TypeId, Vec<String>>,
+    phases: HashMap<TypeId, u32>,
+    supervision: Supervision,
     #[cfg(feature = "cache")]
     cache: Option<Cache>,
+    #[cfg(feature = "cache")]
+    cache_report: Option<CacheReport>,
+    #[cfg(feature = "cache")]
+    cache_file: Option<PathBuf>,
 }
 
 impl InitTree {
@@ -79,505 +402,4119 @@ impl InitTree {
         prior
     }
 
-    /// Request that this tree initialize the provided type T
-    pub fn add<T: 'static + Init>(&mut self) {
-        self.uninitialized.push(T::self_def());
-        T::deep_deps_list(&mut self.uninitialized, 0);
+    /// Keeps the cache in a file, enabling caching. When the tree is initialized the cache is
+    /// loaded from this file, and if it wasn't completely correct the new cache is written back
+    /// to it afterwards.
+    ///
+    /// A missing, unreadable or corrupt file is treated as if there was no cache. The file is
+    /// replaced atomically, so an interrupted write never leaves a partial cache behind. Failing
+    /// to write the cache doesn't fail initialization, as the cache is only an optimization.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use init_tree::InitTree;
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Config>();
+    /// tree.with_cache_file("init_tree.cache");
+    /// let initialized = tree.init();
+    /// ```
+    #[cfg(feature = "cache")]
+    pub fn with_cache_file(&mut self, path: impl Into<PathBuf>) {
+        self.enable_caching(true);
+        self.cache_file = Some(path.into());
     }
 
-    /// Initializes the tree, returning a fully initialized tree, and if caching is enabled, a
-    /// cache from this initialization.
-    pub fn init(mut self) -> InitializedTree {
-        let mut initialized = HashMap::new();
-        self.uninitialized.sort_by_key(|t| t.id);
-        self.uninitialized.dedup_by_key(|t| t.id);
+    /// Records how long each type takes to initialize, available from
+    /// `InitializedTree::timings()`. The timings are also shown when exporting the graph of the
+    /// initialized tree.
+    pub fn record_timings(&mut self, enabled: bool) {
+        self.timings = if enabled {
+            Some(timing::Timings::new())
+        } else {
+            None
+        };
+    }
+
+    /// Registers an observer to be told as each type is initialized, skipped or fails. Observers
+    /// are kept by the `InitializedTree`, and are told about types rebuilt by `reinit()` too.
+    pub fn observe(&mut self, observer: Arc<dyn InitObserver>) {
+        self.supervision.observers.push(observer);
+    }
+
+    /// Catches panics in constructors. Rather than unwinding out of `init()`, a panic stops
+    /// initialization with `InitError::Panicked`, naming the type that panicked, and the
+    /// structures initialized so far are dropped in the reverse of the order they were
+    /// initialized in. `init()` still panics, but with a message saying which type panicked.
+    ///
+    /// The panic hook is still called as usual, so the panic is printed unless the hook has been
+    /// replaced.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitError, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     panic!("no GPU found")
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// tree.catch_panics(true);
+    /// match tree.try_init() {
+    ///     Err(InitError::Panicked { node, message, .. }) => {
+    ///         assert_eq!(node, "Renderer");
+    ///         assert_eq!(message, "no GPU found");
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn catch_panics(&mut self, enabled: bool) {
+        self.supervision.catch_panics = enabled;
+    }
+
+    /// Stops initialization once `token` is cancelled, returning `InitError::Cancelled`. No
+    /// constructors are started after that, and everything initialized so far is dropped in the
+    /// reverse of the order it was initialized in. Applies to `try_init()`, `init_with_plan()`,
+    /// `init_phase()` and `init_incremental()`. `init()` panics with the error instead, so use
+    /// `try_init()` to handle cancellation.
+    pub fn cancel_with(&mut self, token: CancellationToken) {
+        self.supervision.cancel = Some(token);
+    }
+
+    /// Tags T, such as with the team that owns it or the subsystem it belongs to. Tags are
+    /// included in the `GraphManifest` of this tree, and don't affect initialization.
+    pub fn tag<T: 'static>(&mut self, tag: impl Into<String>) {
+        self.tags
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .push(tag.into());
+    }
+
+    /// Puts T in `phase`, so it's initialized by `init_phase()` for that phase or any later
+    /// one. Types without a phase are initialized in the earliest phase of the types waiting
+    /// for them, or once everything else is finished if nothing waits for them.
+    ///
+    /// A type can't wait for a type in a later phase, which `validate()` and `init_phase()`
+    /// report as `InitError::PhaseOrder`.
+    pub fn phase<T: 'static>(&mut self, phase: u32) {
+        self.phases.insert(TypeId::of::<T>(), phase);
+    }
+
+    /// Initializes only the types in `phase` or earlier ones, as set with `phase()`, returning a
+    /// partially initialized tree to continue from later, such as to show a splash screen once
+    /// logging is set up. Caches and plans aren't used when initializing in phases.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Logger;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_logger: &mut Logger) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// tree.add::<Logger>();
+    /// tree.phase::<Logger>(0);
+    /// let early = tree.init_phase(0).unwrap();
+    /// assert!(early.get::<Logger>().is_some());
+    /// assert!(early.get::<Renderer>().is_none());
+    /// let mut initialized = early.finish().unwrap();
+    /// assert!(initialized.take::<Renderer>().is_some());
+    /// ```
+    pub fn init_phase(mut self, phase: u32) -> Result<PartialTree, InitError> {
+        self.prepare(None)?;
+        let (phases, problems) = self.node_phases();
+        if let Some(problem) = problems.into_iter().next() {
+            return Err(problem);
+        }
+        let phases = self
+            .uninitialized
+            .iter()
+            .map(|d| (d.id)())
+            .zip(phases)
+            .collect();
+        if let Some(timings) = &mut self.timings {
+            timings.restart();
+        }
         #[cfg(feature = "cache")]
-        let mut cache_was_correct = self.cache.is_some();
+        {
+            self.cache = None;
+            self.cache_file = None;
+        }
+        let mut partial = PartialTree {
+            tree: self,
+            initialized: internal::Instances::new(None),
+            phases,
+        };
+        partial.init_until(phase)?;
+        Ok(partial)
+    }
+
+    /// Prepares to initialize this tree a few types at a time with `IncrementalInit::step()`,
+    /// such as once per frame so the main thread is never blocked for long. The loaded cache is
+    /// replayed as usual.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// # use std::time::Duration;
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// let mut init = tree.init_incremental().unwrap();
+    /// loop {
+    ///     let progress = init.step(Duration::from_millis(4)).unwrap();
+    ///     println!("{}/{}", progress.done, progress.total);
+    ///     if progress.is_finished() {
+    ///         break;
+    ///     }
+    /// }
+    /// let mut initialized = init.finish().unwrap();
+    /// assert!(initialized.take::<Renderer>().is_some());
+    /// ```
+    pub fn init_incremental(mut self) -> Result<IncrementalInit, InitError> {
         #[cfg(feature = "cache")]
         {
-            if let Some(Cache {
-                inner: CacheVersion::V1(cache),
-            }) = self.cache.as_mut()
-            {
-                // This cache may be invalid. So we're going to replace it
-                // with whatever we learn from this run.
-                let mut new_cache = Vec::new();
-                for i in cache.iter() {
-                    if self.uninitialized.len() <= *i {
-                        continue;
-                    }
-                    let mut new_init = self.uninitialized.swap_remove(*i);
-                    if let Some(new_value) = (new_init.init)(&mut initialized) {
-                        initialized.insert((new_init.id)(), RefCell::new(new_value));
-                        new_cache.push(*i);
-                    } else {
-                        // If we couldn't initialize the value undo the swap_remove
-                        if self.uninitialized.len() > *i {
-                            swap(&mut self.uninitialized[*i], &mut new_init);
-                        }
-                        self.uninitialized.push(new_init);
-                    }
-                }
-                *cache = new_cache;
+            if let Some(cache) = self.cache_file.as_deref().and_then(Cache::read_file) {
+                self.cache = Some(cache);
             }
         }
-        if self.init_cycle(&mut initialized) > 0 {
+        self.prepare(None)?;
+        if let Some(timings) = &mut self.timings {
+            timings.restart();
+        }
+        #[cfg(feature = "cache")]
+        let fingerprint = graph::fingerprint(&self.uninitialized, &self.constraints);
+        #[cfg(feature = "cache")]
+        let mut report = CacheReport::default();
+        #[cfg(feature = "cache")]
+        let cached = self.cached_order(fingerprint, &mut report);
+        Ok(IncrementalInit {
+            total: self.uninitialized.len(),
+            tree: self,
+            initialized: internal::Instances::new(None),
             #[cfg(feature = "cache")]
-            {
-                cache_was_correct = false;
-            }
+            fingerprint,
+            #[cfg(feature = "cache")]
+            cached,
+            #[cfg(feature = "cache")]
+            replay_position: 0,
+            #[cfg(feature = "cache")]
+            replayed: None,
+            #[cfg(feature = "cache")]
+            report,
+        })
+    }
 
-            while self.init_cycle(&mut initialized) > 0 {}
-        }
-        if !self.uninitialized.is_empty() {
-            panic!(
-                "Unable to resolve initialization tree. Locked on [{}]",
-                internal::get_type_names(self.uninitialized.iter())
-            );
+    /// Request that this tree initialize the provided type T
+    ///
+    /// Initialization order only depends on the order types are added in: whenever several types
+    /// could be initialized next, the one added first goes first, and the dependencies of a type
+    /// go in the order they're declared. The same tree is always initialized in the same order.
+    pub fn add<T: 'static + Init>(&mut self) {
+        self.constraints.singletons.insert(TypeId::of::<T>());
+        self.uninitialized.push(T::self_def());
+        T::deep_deps_list(&mut self.uninitialized, 0);
+    }
+
+    /// Initializes `B` after `A`, without `B` taking `A` as a dependency, such as when `A` has
+    /// side effects `B` relies on. Like a dependency, `B` is also dropped before `A`. Neither
+    /// type is added to the tree by this, and the ordering is ignored if `A` isn't in it.
+    ///
+    /// The same can be declared in `impl_init!` with an `after::<A>()` argument, which also adds
+    /// `A` to the tree.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{InitTree, InitObserver, NodeInfo};
+    /// # use std::{sync::{Arc, Mutex}, time::Duration};
+    /// #[derive(Default)]
+    /// struct CrashReporter;
+    ///
+    /// #[derive(Default)]
+    /// struct Renderer;
+    ///
+    /// #[derive(Default)]
+    /// struct Started(Mutex<Vec<&'static str>>);
+    ///
+    /// impl InitObserver for Started {
+    ///     fn on_start(&self, node: NodeInfo) {
+    ///         self.0.lock().unwrap().push(node.name);
+    ///     }
+    /// }
+    ///
+    /// let started = Arc::new(Started::default());
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// tree.add::<CrashReporter>();
+    /// tree.order::<CrashReporter, Renderer>();
+    /// tree.observe(started.clone());
+    /// tree.init();
+    /// assert!(started.0.lock().unwrap()[0].ends_with("CrashReporter"));
+    /// ```
+    pub fn order<A: 'static, B: 'static>(&mut self) {
+        self.constraints
+            .order
+            .push((TypeId::of::<A>(), TypeId::of::<B>()));
+    }
+
+    /// Registers T as a task, which is initialized like any other type but only for the side
+    /// effects of its constructor, such as running database migrations. The constructed T is
+    /// dropped straight away rather than stored, so `InitializedTree::take()` never returns it.
+    ///
+    /// Other types can wait for a task to finish with an `after::<T>()` argument or
+    /// `order()`, but can't borrow or consume it, which is reported as
+    /// `InitError::TaskDependency`. Tasks are timed and planned like other types.
+    ///
+    /// A task is a type rather than a name, a list of dependencies and a closure on purpose.
+    /// Every node in a tree is identified by its `TypeId`, which is what caches, plans, `order()`
+    /// and `after::<T>()` refer to, so a task needs a type for anything to wait for it. That type
+    /// is usually a unit struct, so constructing and dropping it costs nothing, and its
+    /// dependencies are declared with `impl_init!` like those of any other type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Database {
+    ///     version: u32,
+    /// }
+    ///
+    /// struct Migrations;
+    ///
+    /// impl_init!(Migrations; (db: &mut Database) {
+    ///     db.version = 2;
+    ///     Migrations
+    /// });
+    ///
+    /// struct Server(u32);
+    ///
+    /// impl_init!(Server; (db: &mut Database, after::<Migrations>()) {
+    ///     Server(db.version)
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add_task::<Migrations>();
+    /// tree.add::<Server>();
+    /// let mut initialized = tree.init();
+    /// assert_eq!(initialized.take::<Server>().unwrap().0, 2);
+    /// assert!(initialized.take::<Migrations>().is_none());
+    /// ```
+    pub fn add_task<T: 'static + Init>(&mut self) {
+        self.constraints.singletons.remove(&TypeId::of::<T>());
+        self.constraints.tasks.insert(TypeId::of::<T>());
+        self.uninitialized.push(T::self_def());
+        T::deep_deps_list(&mut self.uninitialized, 0);
+    }
+
+    /// Registers T as transient. Rather than being initialized once and stored, a fresh T is
+    /// constructed every time a dependent calls `make()` on a `Factory<T>` argument. The
+    /// dependencies of T are still initialized up front, before anything depending on T.
+    ///
+    /// Types only ever requested through `Factory<T>` are transient without calling this. If
+    /// another type borrows T directly, a singleton T is initialized and stored as well.
+    pub fn add_transient<T: 'static + Init>(&mut self) {
+        self.uninitialized.push(T::self_def());
+        T::deep_deps_list(&mut self.uninitialized, 0);
+    }
+
+    /// Initializes the tree, returning a fully initialized tree, and if caching is enabled, a
+    /// cache from this initialization.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree can't be initialized. Use `try_init()` to handle this instead.
+    pub fn init(self) -> InitializedTree {
+        self.try_init().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Initializes the tree, returning a fully initialized tree, or an error if some of the types
+    /// in it couldn't be initialized.
+    pub fn try_init(self) -> Result<InitializedTree, InitError> {
+        self.init_inner(None)
+    }
+
+    /// Computes the order this tree would be initialized in, without constructing anything.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// let plan = tree.plan().unwrap();
+    /// assert_eq!(plan.steps[1].name, "Renderer");
+    /// assert_eq!(plan.batches().len(), 2);
+    /// let initialized = tree.init_with_plan(&plan).unwrap();
+    /// ```
+    pub fn plan(&self) -> Result<InitPlan, InitError> {
+        let mut tree = self.clone();
+        tree.prepare(None)?;
+        let graph = graph::Graph::new(&tree.uninitialized, &tree.constraints);
+        let levels = graph.levels().map_err(|locked| InitError::Unresolved {
+            locked: locked.iter().map(|i| tree.uninitialized[*i].name).collect(),
+        })?;
+        // Follow the same queue as initialization, so the steps are in the order `init()` uses.
+        let mut queue = graph::ReadyQueue::new(&graph);
+        let mut steps = Vec::with_capacity(levels.len());
+        while let Some(i) = queue.pop() {
+            queue.complete(i);
+            let def = &tree.uninitialized[i];
+            steps.push(PlanStep {
+                name: def.name.to_owned(),
+                type_name: def.type_name.to_owned(),
+                level: levels[i],
+                dependencies: graph.predecessors[i]
+                    .iter()
+                    .map(|p| tree.uninitialized[*p].type_name.to_owned())
+                    .collect(),
+            });
         }
-        InitializedTree {
-            tree: initialized
-                .into_iter()
-                .map(|(k, v)| (k, v.into_inner()))
-                .collect(),
+        Ok(InitPlan {
+            fingerprint: graph::fingerprint(&tree.uninitialized, &tree.constraints),
+            steps,
+        })
+    }
+
+    /// Initializes the tree in the order given by a plan from `plan()`, which may have been
+    /// computed by an earlier run. Fails if the plan was computed from a different dependency
+    /// graph. The cache, if any, isn't used.
+    pub fn init_with_plan(self, plan: &InitPlan) -> Result<InitializedTree, InitError> {
+        self.init_inner(Some(plan))
+    }
+
+    fn init_inner(mut self, plan: Option<&InitPlan>) -> Result<InitializedTree, InitError> {
+        let initialized = self.init_within(None, plan)?;
+        Ok(self.into_initialized(initialized))
+    }
 
+    fn into_initialized(self, initialized: internal::Instances<'static>) -> InitializedTree {
+        InitializedTree {
+            initialized,
+            built: self.built,
+            constraints: self.constraints,
+            timings: self.timings,
+            tags: self.tags,
+            supervision: self.supervision,
             #[cfg(feature = "cache")]
             cache: self.cache,
             #[cfg(feature = "cache")]
-            cache_was_correct,
+            cache_report: self.cache_report,
+        }
+    }
+
+    /// Works out the phase each type is initialized in, in the same order as `uninitialized`,
+    /// along with the types waiting for types in a later phase.
+    fn node_phases(&self) -> (Vec<u32>, Vec<InitError>) {
+        let graph = graph::Graph::new(&self.uninitialized, &self.constraints);
+        let explicit = self
+            .uninitialized
+            .iter()
+            .map(|d| self.phases.get(&(d.id)()).copied())
+            .collect::<Vec<_>>();
+        let phases = graph.phases(&explicit);
+        let mut problems = Vec::new();
+        for (node, predecessors) in graph.predecessors.iter().enumerate() {
+            for &p in predecessors {
+                if phases[p] > phases[node] {
+                    problems.push(InitError::PhaseOrder {
+                        node: self.uninitialized[node].name,
+                        phase: phases[node],
+                        dependency: self.uninitialized[p].name,
+                        dependency_phase: phases[p],
+                    });
+                }
+            }
         }
+        (phases, problems)
     }
 
-    fn init_cycle(&mut self, initialized: &mut HashMap<TypeId, RefCell<Box<dyn Any>>>) -> u32 {
-        let mut initialized_count = 0;
-        let mut i = 0;
-        while i < self.uninitialized.len() {
-            if (self.uninitialized[i].deps)()
+    /// Removes duplicate types and types provided by `parent`, and works out the scheduling
+    /// rules for the remaining types.
+    fn prepare(&mut self, parent: Option<&internal::Instances>) -> Result<(), InitError> {
+        if let Some(conflict) = self.schedule().into_iter().next() {
+            return Err(conflict);
+        }
+        if let Some(parent) = parent {
+            let singletons = &self.constraints.singletons;
+            self.uninitialized.retain(|t| {
+                let id = (t.id)();
+                if singletons.contains(&id) {
+                    parent.get(&id).is_none()
+                } else {
+                    !parent.is_ready(&id)
+                }
+            });
+            let ids = self
+                .uninitialized
                 .iter()
-                .all(|t| initialized.contains_key(&(t.id)()))
-            {
-                let new_init = self.uninitialized.swap_remove(i);
-                if let Some(new_value) = (new_init.init)(initialized) {
-                    initialized.insert((new_init.id)(), RefCell::new(new_value));
-                    initialized_count += 1;
-                    #[cfg(feature = "cache")]
+                .map(|t| (t.id)())
+                .collect::<HashSet<_>>();
+            for def in &self.uninitialized {
+                for dep in (def.deps)() {
+                    let dep_def = (dep.def)();
+                    let dep_id = (dep_def.id)();
+                    if dep.kind == internal::DependencyKind::Consume
+                        && !ids.contains(&dep_id)
+                        && parent.get(&dep_id).is_some()
                     {
-                        if let Some(Cache {
-                            inner: CacheVersion::V1(cache),
-                        }) = self.cache.as_mut()
-                        {
-                            cache.push(i);
-                        }
+                        return Err(InitError::ConsumedFromParent {
+                            node: def.name,
+                            dependency: dep_def.name,
+                        });
                     }
                 }
-            } else {
-                i += 1;
             }
         }
-        initialized_count
+        Ok(())
     }
-}
 
-/// A collection of all the structures after they've been initialized. Call `.take::<MyType>()` on
-/// this to obtain the newly initialized structure.
-#[derive(Default)]
-pub struct InitializedTree {
-    tree: HashMap<TypeId, Box<dyn Any>>,
-    #[cfg(feature = "cache")]
-    cache: Option<Cache>,
-    #[cfg(feature = "cache")]
-    cache_was_correct: bool,
-}
+    /// Removes duplicate types and works out the scheduling rules for the rest, returning the
+    /// conflicts between types taking the same dependency by value.
+    ///
+    /// Types are identified by their `TypeId`. The first registration of a type is kept, and
+    /// later ones are dropped if they provide it the same way or reported if they don't.
+    fn schedule(&mut self) -> Vec<InitError> {
+        let mut conflicts = Vec::new();
+        let mut providers = HashMap::<TypeId, internal::TypeInitDef>::new();
+        self.uninitialized
+            .retain(|t| match providers.entry((t.id)()) {
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(*t);
+                    true
+                }
+                hash_map::Entry::Occupied(entry) => {
+                    if !entry.get().same_provider(t) {
+                        conflicts.push(InitError::DuplicateProvider {
+                            node: t.name,
+                            first: entry.get().signature(),
+                            second: t.signature(),
+                        });
+                    }
+                    false
+                }
+            });
+        for &(before, after) in &self.constraints.order {
+            if before != after && providers.contains_key(&before) {
+                self.constraints
+                    .after
+                    .entry(after)
+                    .or_default()
+                    .push(before);
+            }
+        }
+        let mut consumers = HashMap::<TypeId, Vec<&internal::TypeInitDef>>::new();
+        let mut readers = HashMap::<TypeId, Vec<TypeId>>::new();
+        for def in &self.uninitialized {
+            Self::find_aliased_borrows(def, &mut conflicts);
+            for dep in (def.deps)() {
+                let dep_id = ((dep.def)().id)();
+                let exclusive = matches!(
+                    dep.kind,
+                    internal::DependencyKind::Borrow | internal::DependencyKind::Consume
+                );
+                if exclusive && self.constraints.tasks.contains(&dep_id) {
+                    conflicts.push(InitError::TaskDependency {
+                        node: def.name,
+                        task: (dep.def)().name,
+                    });
+                }
+                match dep.kind {
+                    internal::DependencyKind::Factory => continue,
+                    internal::DependencyKind::Consume => {
+                        consumers.entry(dep_id).or_default().push(def)
+                    }
+                    internal::DependencyKind::Borrow => {
+                        readers.entry(dep_id).or_default().push((def.id)())
+                    }
+                    internal::DependencyKind::Late | internal::DependencyKind::After => {}
+                }
+                if !self.constraints.tasks.contains(&dep_id) {
+                    self.constraints.singletons.insert(dep_id);
+                }
+            }
+        }
+        let consumed = consumers.keys().copied().collect::<HashSet<_>>();
+        Self::find_consumed_while_needed(&self.uninitialized, &consumed, &mut conflicts);
+        // A consumed value is moved out of the tree, so its consumer has to wait for everything
+        // else borrowing it.
+        for (dep_id, mut consumers) in consumers {
+            consumers.dedup_by_key(|d| (d.id)());
+            if consumers.len() > 1 {
+                let mut consumers = consumers.iter().map(|d| d.name).collect::<Vec<_>>();
+                consumers.sort_unstable();
+                conflicts.push(InitError::MultipleConsumers {
+                    dependency: self
+                        .uninitialized
+                        .iter()
+                        .find(|d| (d.id)() == dep_id)
+                        .map_or("<Unknown Type>", |d| d.name),
+                    consumers,
+                });
+                continue;
+            }
+            if let Some(readers) = readers.remove(&dep_id) {
+                let consumer = (consumers[0].id)();
+                self.constraints
+                    .after
+                    .entry(consumer)
+                    .or_default()
+                    .extend(readers.into_iter().filter(|r| *r != consumer));
+            }
+        }
+        conflicts.sort_by_key(InitError::to_string);
+        conflicts.dedup();
+        conflicts
+    }
 
-impl InitializedTree {
-    /// Removes the initialized structure from this tree and returns it.
-    pub fn take<T: 'static>(&mut self) -> Option<T> {
-        self.tree
-            .remove(&TypeId::of::<T>())
-            .map(|v| *v.downcast::<T>().unwrap())
+    /// Finds the types needing a value after it has been moved out of the tree. Transients are
+    /// constructed again on every `Factory::make()`, which can happen after the value was
+    /// consumed, or consume it a second time, and `Late` handles are only filled in once
+    /// everything has been initialized.
+    fn find_consumed_while_needed(
+        defs: &[internal::TypeInitDef],
+        consumed: &HashSet<TypeId>,
+        conflicts: &mut Vec<InitError>,
+    ) {
+        let mut products = HashSet::new();
+        for def in defs {
+            for dep in (def.deps)() {
+                let dep_def = (dep.def)();
+                match dep.kind {
+                    internal::DependencyKind::Factory => {
+                        products.insert((dep_def.id)());
+                    }
+                    internal::DependencyKind::Late if consumed.contains(&(dep_def.id)()) => {
+                        conflicts.push(InitError::ConsumedWhileNeeded {
+                            node: def.name,
+                            dependency: dep_def.name,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for def in defs.iter().filter(|d| products.contains(&(d.id)())) {
+            for dep in (def.deps)() {
+                let dep_def = (dep.def)();
+                let taken = match dep.kind {
+                    internal::DependencyKind::Consume => true,
+                    internal::DependencyKind::Borrow => consumed.contains(&(dep_def.id)()),
+                    _ => false,
+                };
+                if taken {
+                    conflicts.push(InitError::ConsumedWhileNeeded {
+                        node: def.name,
+                        dependency: dep_def.name,
+                    });
+                }
+            }
+        }
     }
 
-    /// Returns an iterator of all initialized types.
-    pub fn take_all(self) -> impl Iterator<Item = (TypeId, Box<dyn Any>)> {
-        self.tree.into_iter()
+    /// Finds the dependencies `def` borrows or consumes more than once, either directly or
+    /// through the products of its `Factory` dependencies, which borrow their own dependencies
+    /// while `def` still holds its own. The constructor would otherwise panic when borrowing the
+    /// dependency a second time.
+    fn find_aliased_borrows(def: &internal::TypeInitDef, conflicts: &mut Vec<InitError>) {
+        let exclusive = |dep: &internal::Dependency| {
+            matches!(
+                dep.kind,
+                internal::DependencyKind::Borrow | internal::DependencyKind::Consume
+            )
+        };
+        let mut reported = HashSet::new();
+        let mut report = |dep_def: internal::TypeInitDef| {
+            if reported.insert((dep_def.id)()) {
+                conflicts.push(InitError::AliasedBorrow {
+                    node: def.name,
+                    dependency: dep_def.name,
+                });
+            }
+        };
+        let mut held = HashSet::new();
+        for dep in (def.deps)().iter().filter(|d| exclusive(d)) {
+            let dep_def = (dep.def)();
+            if !held.insert((dep_def.id)()) {
+                report(dep_def);
+            }
+        }
+        let factories = (def.deps)()
+            .iter()
+            .filter(|d| d.kind == internal::DependencyKind::Factory);
+        for factory in factories {
+            let mut visited = HashSet::new();
+            let mut pending = vec![(factory.def)()];
+            while let Some(product) = pending.pop() {
+                if !visited.insert((product.id)()) {
+                    continue;
+                }
+                for dep in (product.deps)() {
+                    let dep_def = (dep.def)();
+                    if dep.kind == internal::DependencyKind::Factory {
+                        pending.push(dep_def);
+                    } else if exclusive(dep) && held.contains(&(dep_def.id)()) {
+                        report(dep_def);
+                    }
+                }
+            }
+        }
     }
 
-    /// Removes the initialized structure from this tree and returns it. Prefer `take()` if possible,
-    /// but this function is provided in case the type can't be determined at compile time.
-    pub fn take_by_type_id(&mut self, t: TypeId) -> Option<Box<dyn Any>> {
-        self.tree.remove(&t)
+    /// Initializes every type in this tree that isn't already provided by `parent` or one of its
+    /// ancestors, following `plan` if there is one.
+    fn init_within<'p>(
+        &mut self,
+        parent: Option<&'p internal::Instances<'p>>,
+        plan: Option<&InitPlan>,
+    ) -> Result<internal::Instances<'p>, InitError> {
+        #[cfg(feature = "cache")]
+        {
+            if let Some(cache) = self.cache_file.as_deref().and_then(Cache::read_file) {
+                self.cache = Some(cache);
+            }
+        }
+        let mut initialized = internal::Instances::new(parent);
+        self.prepare(parent)?;
+        #[cfg(feature = "tracing")]
+        let _span = trace::tree_span(self.uninitialized.len()).entered();
+        if let Some(timings) = &mut self.timings {
+            timings.restart();
+        }
+        let fingerprint = graph::fingerprint(&self.uninitialized, &self.constraints);
+        if let Some(plan) = plan {
+            if plan.fingerprint != fingerprint {
+                return Err(InitError::PlanMismatch {
+                    planned: plan.fingerprint,
+                    current: fingerprint,
+                });
+            }
+            let mut locked = false;
+            self.replay(
+                plan.steps.iter().map(|s| s.type_name.as_str()),
+                false,
+                &mut initialized,
+                |_, outcome| locked |= outcome != Replayed::Applied,
+            )?;
+            if locked {
+                return Err(self.unresolved(&initialized));
+            }
+        }
+        #[cfg(feature = "cache")]
+        let mut report = CacheReport::default();
+        #[cfg(feature = "cache")]
+        {
+            if plan.is_none() {
+                let order = self.cached_order(fingerprint, &mut report);
+                self.replay(
+                    order.iter().map(String::as_str),
+                    true,
+                    &mut initialized,
+                    |name, outcome| report.record(name, outcome),
+                )?;
+            }
+        }
+        #[cfg(feature = "cache")]
+        let replayed = self.built.len();
+        self.init_ready(&mut initialized, || true)?;
+        if !self.uninitialized.is_empty() {
+            return Err(self.unresolved(&initialized));
+        }
+        initialized.resolve_late()?;
+        // A plan replaces the cache, so there's nothing to report on or store.
+        #[cfg(feature = "cache")]
+        if plan.is_none() {
+            self.store_cache(fingerprint, replayed, report);
+        }
+        Ok(initialized)
     }
 
-    /// Return the cache from this initialization.
+    /// Returns the order the loaded cache says this tree is initialized in, or nothing if there
+    /// is no cache or it doesn't match the tree.
     #[cfg(feature = "cache")]
-    pub fn take_cache(&mut self) -> Option<Cache> {
-        self.cache.take()
+    fn cached_order(&self, fingerprint: u64, report: &mut CacheReport) -> Vec<String> {
+        match self.cache.as_ref().map(|c| c.order(fingerprint)) {
+            Some(Ok(order)) => order.to_vec(),
+            Some(Err(rejection)) => {
+                report.rejected = Some(rejection);
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
     }
 
-    /// Returns true if the cache loaded in was completely correct.
+    /// Replaces the loaded cache with the order this tree was just initialized in, writing it
+    /// to the cache file if it changed. `replayed` is the number of types initialized from the
+    /// loaded cache.
     #[cfg(feature = "cache")]
-    pub fn cache_was_correct(&self) -> bool {
-        self.cache_was_correct
+    fn store_cache(&mut self, fingerprint: u64, replayed: usize, mut report: CacheReport) {
+        if self.cache.is_some() {
+            report.discovered = self.built[replayed..].iter().map(|d| d.name).collect();
+            let cache = Cache::new(fingerprint, &self.built);
+            if let Some(path) = &self.cache_file {
+                if !report.is_correct() {
+                    let _ = cache.write_file(path);
+                }
+            }
+            self.cache = Some(cache);
+            self.cache_report = Some(report);
+        }
     }
-}
 
-/// The trait that must be implemented for a type before it can be used with the `InitTree`.
-/// Automatically implemented for `Default` types.
+    /// Checks the whole tree for problems that would stop it from being initialized, without
+    /// constructing anything. Rather than stopping at the first problem, every one found is
+    /// returned.
+    ///
+    /// Checks for dependencies nothing provides, circular dependencies, different types
+    /// registered under the same full name, types taking the same dependency by value more than
+    /// once, types taking a dependency more than once while also borrowing or consuming it, and
+    /// types waiting for a type in a later phase.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitError, InitTree};
+    /// struct Chicken;
+    ///
+    /// impl_init!(Chicken; (_egg: &mut Egg) {
+    ///     Chicken
+    /// });
+    ///
+    /// struct Egg;
+    ///
+    /// impl_init!(Egg; (_chicken: &mut Chicken) {
+    ///     Egg
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Chicken>();
+    /// assert_eq!(
+    ///     tree.validate(),
+    ///     Err(vec![InitError::Cycle {
+    ///         nodes: vec!["Chicken", "Egg"]
+    ///     }])
+    /// );
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<InitError>> {
+        let mut tree = self.clone();
+        let mut problems = tree.schedule();
+        let mut ids = HashSet::new();
+        let mut names = HashMap::<&str, TypeId>::new();
+        let mut duplicates = HashSet::new();
+        for def in &tree.uninitialized {
+            let id = (def.id)();
+            ids.insert(id);
+            if *names.entry(def.type_name).or_insert(id) != id {
+                duplicates.insert(def.type_name);
+            }
+        }
+        let mut duplicates = duplicates.into_iter().collect::<Vec<_>>();
+        duplicates.sort_unstable();
+        problems.extend(
+            duplicates
+                .into_iter()
+                .map(|type_name| InitError::DuplicateBinding { type_name }),
+        );
+        let mut defs = tree.uninitialized.iter().collect::<Vec<_>>();
+        defs.sort_by_key(|d| d.type_name);
+        for def in defs {
+            for dep in (def.deps)() {
+                let dep_def = (dep.def)();
+                if !ids.contains(&(dep_def.id)()) {
+                    problems.push(InitError::MissingProvider {
+                        node: def.name,
+                        dependency: dep_def.name,
+                    });
+                }
+            }
+        }
+        let graph = graph::Graph::new(&tree.uninitialized, &tree.constraints);
+        problems.extend(graph.cycles().into_iter().map(|cycle| {
+            let mut nodes = cycle
+                .into_iter()
+                .map(|i| tree.uninitialized[i].name)
+                .collect::<Vec<_>>();
+            nodes.sort_unstable();
+            InitError::Cycle { nodes }
+        }));
+        problems.extend(tree.node_phases().1);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Draws the dependency graph of this tree in Graphviz DOT, with an edge from each type to
+    /// each of its dependencies. Nothing is initialized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, ExportOptions, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// let options = ExportOptions {
+    ///     roots: true,
+    ///     ..Default::default()
+    /// };
+    /// assert!(tree.to_dot(&options).contains("label=\"Renderer\""));
+    /// ```
+    pub fn to_dot(&self, options: &ExportOptions) -> String {
+        self.diagram(options).to_dot()
+    }
+
+    /// Draws the dependency graph of this tree as a Mermaid flowchart, with an edge from each
+    /// type to each of its dependencies. Nothing is initialized.
+    pub fn to_mermaid(&self, options: &ExportOptions) -> String {
+        self.diagram(options).to_mermaid()
+    }
+
+    fn diagram(&self, options: &ExportOptions) -> export::Diagram {
+        let mut tree = self.clone();
+        tree.schedule();
+        export::Diagram::new(&tree.uninitialized, &tree.constraints, None, options)
+    }
+
+    /// Describes every type in this tree and the dependencies between them, such as to feed
+    /// into other tools or to compare with the graph of another build. Nothing is initialized.
+    pub fn manifest(&self) -> GraphManifest {
+        let mut tree = self.clone();
+        tree.schedule();
+        GraphManifest::new(&tree.uninitialized, &tree.constraints, &tree.tags)
+    }
+
+    /// Initializes types in the given order, identified by their full names, reporting the
+    /// outcome of each one. `cached` is true if the order came from the cache.
+    fn replay<'a>(
+        &mut self,
+        order: impl Iterator<Item = &'a str>,
+        cached: bool,
+        initialized: &mut internal::Instances,
+        mut outcome: impl FnMut(&'a str, Replayed),
+    ) -> Result<(), InitError> {
+        let positions = self
+            .uninitialized
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (d.type_name, i))
+            .collect::<HashMap<_, _>>();
+        let mut done = vec![false; self.uninitialized.len()];
+        let mut result = Ok(());
+        let mut cancelled = false;
+        for name in order {
+            let Some(&i) = positions.get(name).filter(|i| !done[**i]) else {
+                outcome(name, Replayed::Skipped);
+                continue;
+            };
+            if self.supervision.cancelled() {
+                cancelled = true;
+                break;
+            }
+            let def = self.uninitialized[i];
+            match Self::construct(
+                &def,
+                &self.constraints,
+                &self.supervision,
+                cached,
+                initialized,
+            ) {
+                Ok(Some((started, elapsed))) => {
+                    self.record(&def, started, elapsed);
+                    done[i] = true;
+                    outcome(name, Replayed::Applied);
+                }
+                Ok(None) => outcome(name, Replayed::NotReady),
+                Err(error) => {
+                    let defs = self.uninitialized.iter().chain(&self.built);
+                    result = Err(error.with_chain(&def, defs));
+                    break;
+                }
+            }
+        }
+        // Keep the remaining types in the order they were registered in.
+        let mut done = done.into_iter();
+        self.uninitialized.retain(|_| !done.next().unwrap_or(false));
+        if cancelled {
+            return Err(self.cancelled());
+        }
+        result
+    }
+
+    /// Initializes a single type if all of its dependencies are ready, returning when it started
+    /// and how long it took if it was initialized. Transient types are only marked as ready, as
+    /// their dependents construct them. `cached` is true if the type is initialized in the
+    /// position given by the cache.
+    ///
+    /// Returns an error if the constructor failed, or if it panicked and panics are being caught.
+    /// The chain of types that led to a panic isn't filled in.
+    fn construct(
+        def: &internal::TypeInitDef,
+        constraints: &Constraints,
+        supervision: &Supervision,
+        cached: bool,
+        initialized: &mut internal::Instances,
+    ) -> Result<Option<(Instant, Duration)>, InitError> {
+        if !constraints.ready(def, initialized) {
+            return Ok(None);
+        }
+        let observers = &supervision.observers;
+        #[cfg(feature = "tracing")]
+        let span = trace::node_span(def, cached);
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        let node = NodeInfo::from(def);
+        for observer in observers {
+            observer.on_start(node);
+        }
+        let start = Instant::now();
+        let task = constraints.tasks.contains(&node.id);
+        if task || constraints.singletons.contains(&node.id) {
+            let value = if supervision.catch_panics {
+                panic::catch_unwind(AssertUnwindSafe(|| (def.init)(initialized))).map_err(
+                    |payload| InitError::Panicked {
+                        node: def.name,
+                        message: payload
+                            .downcast_ref::<&str>()
+                            .map(|m| m.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "<non-string panic payload>".to_owned()),
+                        chain: Vec::new(),
+                    },
+                )
+            } else {
+                Ok((def.init)(initialized))
+            };
+            initialized.remove_consumed();
+            match value {
+                Ok(Some(_)) if task => initialized.mark_ready(node.id),
+                Ok(Some(value)) => initialized.insert(node.id, value),
+                Ok(None) => {
+                    let error = InitError::Unresolved {
+                        locked: vec![def.name],
+                    };
+                    for observer in observers {
+                        observer.on_failure(node, &error);
+                    }
+                    #[cfg(feature = "tracing")]
+                    span.record("outcome", "failure");
+                    return Err(error);
+                }
+                Err(error) => {
+                    for observer in observers {
+                        observer.on_failure(node, &error);
+                    }
+                    #[cfg(feature = "tracing")]
+                    span.record("outcome", "panicked");
+                    return Err(error);
+                }
+            }
+        } else {
+            initialized.mark_ready(node.id);
+        }
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        span.record("outcome", "success");
+        for observer in observers {
+            observer.on_success(node, elapsed);
+            if cached {
+                observer.on_cache_hit(node);
+            }
+        }
+        Ok(Some((start, elapsed)))
+    }
+
+    /// Builds the error for types that could never be initialized, telling the observers which
+    /// types each of them was waiting for.
+    fn unresolved(&self, initialized: &internal::Instances) -> InitError {
+        if !self.supervision.observers.is_empty() {
+            let names = self
+                .uninitialized
+                .iter()
+                .chain(&self.built)
+                .map(|d| ((d.id)(), NodeInfo::from(d)))
+                .collect::<HashMap<_, _>>();
+            for def in &self.uninitialized {
+                let id = (def.id)();
+                let mut blocked_by = (def.deps)()
+                    .iter()
+                    .filter(|d| d.is_ordering())
+                    .map(|d| NodeInfo::from(&(d.def)()))
+                    .chain(
+                        self.constraints
+                            .after
+                            .get(&id)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|id| names.get(id).copied()),
+                    )
+                    .filter(|n| !initialized.is_ready(&n.id))
+                    .collect::<Vec<_>>();
+                blocked_by.sort_by_key(|n| n.name);
+                blocked_by.dedup();
+                for observer in &self.supervision.observers {
+                    observer.on_skipped(NodeInfo::from(def), &blocked_by);
+                }
+            }
+        }
+        InitError::Unresolved {
+            locked: self.uninitialized.iter().map(|d| d.name).collect(),
+        }
+    }
+
+    /// Notes that a type was initialized.
+    fn record(&mut self, def: &internal::TypeInitDef, started: Instant, elapsed: Duration) {
+        if let Some(timings) = &mut self.timings {
+            timings.record(def, started, elapsed);
+        }
+        self.built.push(*def);
+    }
+
+    /// The error for initialization being cancelled now.
+    fn cancelled(&self) -> InitError {
+        let constructed = |d: &&internal::TypeInitDef| {
+            let id = (d.id)();
+            self.constraints.singletons.contains(&id) || self.constraints.tasks.contains(&id)
+        };
+        InitError::Cancelled {
+            built: self
+                .built
+                .iter()
+                .filter(constructed)
+                .map(|d| d.name)
+                .collect(),
+            unbuilt: self.uninitialized.iter().map(|d| d.name).collect(),
+        }
+    }
+
+    /// Initializes types until none are left with all of their dependencies ready, checking
+    /// `keep_going` before each one. Whenever several types are ready, the one registered first
+    /// is initialized first, so the order is the same on every run and platform. Types are
+    /// registered in the order they're added, each followed by its dependencies in the order
+    /// they're declared.
+    ///
+    /// Returns false if `keep_going` stopped it before it ran out of ready types.
+    fn init_ready(
+        &mut self,
+        initialized: &mut internal::Instances,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> Result<bool, InitError> {
+        let mut queue =
+            graph::ReadyQueue::new(&graph::Graph::new(&self.uninitialized, &self.constraints));
+        let mut done = vec![false; self.uninitialized.len()];
+        let mut remaining = done.len();
+        let mut result = Ok(true);
+        let mut cancelled = false;
+        while remaining > 0 {
+            if self.supervision.cancelled() {
+                cancelled = true;
+                break;
+            }
+            if !keep_going() {
+                result = Ok(false);
+                break;
+            }
+            let Some(i) = queue.pop() else {
+                break;
+            };
+            let def = self.uninitialized[i];
+            match Self::construct(
+                &def,
+                &self.constraints,
+                &self.supervision,
+                false,
+                initialized,
+            ) {
+                Ok(Some((started, elapsed))) => {
+                    self.record(&def, started, elapsed);
+                    done[i] = true;
+                    remaining -= 1;
+                    queue.complete(i);
+                }
+                // Waiting on something outside this tree that isn't ready, so it never will be.
+                Ok(None) => {}
+                Err(error) => {
+                    let defs = self.uninitialized.iter().chain(&self.built);
+                    result = Err(error.with_chain(&def, defs));
+                    break;
+                }
+            }
+        }
+        // Keep the remaining types in the order they were registered in.
+        let mut done = done.into_iter();
+        self.uninitialized.retain(|_| !done.next().unwrap_or(false));
+        if cancelled {
+            return Err(self.cancelled());
+        }
+        result
+    }
+}
+
+/// A collection of all the structures after they've been initialized. Call `.take::<MyType>()` on
+/// this to obtain the newly initialized structure.
 ///
-/// You are discouraged from implementing this manually, and should use the `impl_init` macro
-/// instead.
-pub trait Init: Sized {
-    fn init(initialized: &mut HashMap<TypeId, RefCell<Box<dyn Any>>>) -> Option<Self>;
-    fn self_def() -> internal::TypeInitDef;
-    fn deps_list() -> &'static [internal::TypeInitDef];
-    fn deep_deps_list(t: &mut Vec<internal::TypeInitDef>, call_depth: u32);
+/// Any structures still in the tree when it's dropped are dropped in the reverse of the order
+/// they were initialized in.
+#[derive(Default)]
+pub struct InitializedTree {
+    initialized: internal::Instances<'static>,
+    built: Vec<internal::TypeInitDef>,
+    constraints: Constraints,
+    timings: Option<timing::Timings>,
+    tags: HashMap<TypeId, Vec<String>>,
+    supervision: Supervision,
+    #[cfg(feature = "cache")]
+    cache: Option<Cache>,
+    #[cfg(feature = "cache")]
+    cache_report: Option<CacheReport>,
 }
 
-impl<T: 'static + Default> Init for T {
-    fn init(_: &mut HashMap<TypeId, RefCell<Box<dyn Any>>>) -> Option<Self> {
-        Some(Default::default())
+impl InitializedTree {
+    /// Removes the initialized structure from this tree and returns it.
+    pub fn take<T: 'static>(&mut self) -> Option<T> {
+        self.initialized
+            .remove(&TypeId::of::<T>())
+            .map(|v| *v.downcast::<T>().unwrap())
     }
 
-    fn self_def() -> internal::TypeInitDef {
-        internal::TypeInitDef {
-            id: TypeId::of::<Self>,
-            deps: Self::deps_list,
-            init: |h| Self::init(h).map(|h| Box::new(h) as Box<dyn Any>),
-            name: "<Unknown Type>", // Default type should never have name printed.
+    /// Returns an iterator of all initialized types.
+    pub fn take_all(self) -> impl Iterator<Item = (TypeId, Box<dyn Any>)> {
+        self.initialized.into_values()
+    }
+
+    /// Removes the initialized structure from this tree and returns it. Prefer `take()` if possible,
+    /// but this function is provided in case the type can't be determined at compile time.
+    pub fn take_by_type_id(&mut self, t: TypeId) -> Option<Box<dyn Any>> {
+        self.initialized.remove(&t)
+    }
+
+    /// Rebuilds T and everything that depends on it, directly or indirectly, such as after a
+    /// configuration change. Structures that don't depend on T are kept alive, and are borrowed by
+    /// the rebuilt structures as usual.
+    ///
+    /// The affected structures are dropped in the reverse of the order they were initialized in,
+    /// then initialized again in their original order. Structures that were already taken from
+    /// this tree are rebuilt as well. Returns the rebuilt types in the order they were initialized.
+    ///
+    /// If a rebuilt type depends on a structure that was taken from this tree it can't be
+    /// initialized, and an error listing it and everything after it is returned. Those types are
+    /// left uninitialized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// let mut initialized = tree.init();
+    /// let rebuilt = initialized.reinit::<Config>().unwrap();
+    /// assert_eq!(rebuilt.len(), 2);
+    /// assert_eq!(rebuilt[1].name, "Renderer");
+    /// ```
+    pub fn reinit<T: 'static>(&mut self) -> Result<Vec<NodeInfo>, InitError> {
+        let mut affected = HashSet::new();
+        affected.insert(TypeId::of::<T>());
+        // `Late` dependents may have been initialized before their dependency, so keep going
+        // until nothing new is found.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for def in &self.built {
+                if (def.deps)()
+                    .iter()
+                    .any(|d| affected.contains(&((d.def)().id)()))
+                {
+                    changed |= affected.insert((def.id)());
+                }
+            }
+        }
+        let rebuild = self
+            .built
+            .iter()
+            .filter(|def| affected.contains(&(def.id)()))
+            .copied()
+            .collect::<Vec<_>>();
+        for def in rebuild.iter().rev() {
+            self.initialized.remove(&(def.id)());
+        }
+        let mut rebuilt = Vec::with_capacity(rebuild.len());
+        for (i, def) in rebuild.iter().enumerate() {
+            let constructed = InitTree::construct(
+                def,
+                &self.constraints,
+                &self.supervision,
+                false,
+                &mut self.initialized,
+            )
+            .map_err(|e| e.with_chain(def, self.built.iter()))?;
+            let Some((started, elapsed)) = constructed else {
+                return Err(InitError::Unresolved {
+                    locked: rebuild[i..].iter().map(|d| d.name).collect(),
+                });
+            };
+            if let Some(timings) = &mut self.timings {
+                timings.record(def, started, elapsed);
+            }
+            rebuilt.push(NodeInfo::from(def));
         }
+        self.initialized.resolve_late()?;
+        Ok(rebuilt)
     }
 
-    fn deps_list() -> &'static [internal::TypeInitDef] {
-        &[]
+    /// Initializes the types in `tree` as a child scope of this tree, such as for per-request or
+    /// per-session state. Types in the scope may depend on structures still held by this tree,
+    /// which are borrowed rather than initialized again. This tree can never depend on the scope.
+    ///
+    /// Dropping the returned `ScopedTree` tears down the scoped structures in the reverse of the
+    /// order they were initialized in, leaving this tree untouched.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Database;
+    ///
+    /// struct Session;
+    ///
+    /// impl_init!(Session; (_db: &mut Database) {
+    ///     Session
+    /// });
+    ///
+    /// let mut app = InitTree::new();
+    /// app.add::<Database>();
+    /// let app = app.init();
+    ///
+    /// let mut request = InitTree::new();
+    /// request.add::<Session>();
+    /// let mut scope = app.scope(request);
+    /// assert!(scope.take::<Session>().is_some());
+    /// assert!(scope.take::<Database>().is_none());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scope can't be initialized. Use `try_scope()` to handle this instead.
+    pub fn scope(&self, tree: InitTree) -> ScopedTree<'_> {
+        self.try_scope(tree).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    fn deep_deps_list(_t: &mut Vec<internal::TypeInitDef>, _call_depth: u32) {}
+    /// Initializes the types in `tree` as a child scope of this tree like `scope()`, or returns
+    /// an error if some of them couldn't be initialized.
+    pub fn try_scope(&self, mut tree: InitTree) -> Result<ScopedTree<'_>, InitError> {
+        let initialized = tree.init_within(Some(&self.initialized), None)?;
+        Ok(ScopedTree { initialized })
+    }
+
+    /// Draws the dependency graph of the types initialized in this tree in Graphviz DOT, with an
+    /// edge from each type to each of its dependencies. If timings were recorded with
+    /// `InitTree::record_timings()`, each type is labelled with how long it took to initialize.
+    pub fn to_dot(&self, options: &ExportOptions) -> String {
+        self.diagram(options).to_dot()
+    }
+
+    /// Draws the dependency graph of the types initialized in this tree as a Mermaid flowchart,
+    /// labelled with timings like `to_dot()`.
+    pub fn to_mermaid(&self, options: &ExportOptions) -> String {
+        self.diagram(options).to_mermaid()
+    }
+
+    /// Returns how long each type took to initialize, slowest first, if timings were recorded
+    /// with `InitTree::record_timings()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     std::thread::sleep(std::time::Duration::from_millis(10));
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// tree.record_timings(true);
+    /// let initialized = tree.init();
+    /// assert_eq!(initialized.timings()[0].node.name, "Renderer");
+    /// let critical_path = initialized.critical_path().unwrap();
+    /// assert_eq!(critical_path.nodes.len(), 2);
+    /// ```
+    pub fn timings(&self) -> Vec<NodeTiming> {
+        self.timings
+            .as_ref()
+            .map(timing::Timings::sorted)
+            .unwrap_or_default()
+    }
+
+    /// Finds the chain of types that had to be initialized one after another taking the most
+    /// time, if timings were recorded with `InitTree::record_timings()`.
+    pub fn critical_path(&self) -> Option<CriticalPath> {
+        let timings = &self.timings.as_ref()?.nodes;
+        let elapsed = |d: &internal::TypeInitDef| {
+            timings.get(&(d.id)()).map_or(Duration::ZERO, |t| t.elapsed)
+        };
+        let graph = graph::Graph::new(&self.built, &self.constraints);
+        let weights = self
+            .built
+            .iter()
+            .map(|d| elapsed(d).as_nanos())
+            .collect::<Vec<_>>();
+        let path = graph.critical_path(&weights);
+        let total = self.built.iter().map(elapsed).sum::<Duration>();
+        let critical = path
+            .iter()
+            .map(|i| elapsed(&self.built[*i]))
+            .sum::<Duration>();
+        Some(CriticalPath {
+            nodes: path
+                .iter()
+                .map(|i| NodeInfo::from(&self.built[*i]))
+                .collect(),
+            elapsed: critical,
+            parallelism: if critical.is_zero() {
+                1.0
+            } else {
+                total.as_secs_f64() / critical.as_secs_f64()
+            },
+        })
+    }
+
+    /// Writes the recorded timings in the Chrome trace event format, to view initialization in
+    /// `chrome://tracing` or Perfetto. Returns `None` if timings weren't recorded with
+    /// `InitTree::record_timings()`.
+    pub fn chrome_trace(&self) -> Option<String> {
+        self.timings.as_ref().map(timing::Timings::chrome_trace)
+    }
+
+    /// Describes every type initialized in this tree and the dependencies between them.
+    pub fn manifest(&self) -> GraphManifest {
+        GraphManifest::new(&self.built, &self.constraints, &self.tags)
+    }
+
+    fn diagram(&self, options: &ExportOptions) -> export::Diagram {
+        export::Diagram::new(
+            &self.built,
+            &self.constraints,
+            self.timings.as_ref().map(|t| &t.nodes),
+            options,
+        )
+    }
+
+    /// Return the cache from this initialization.
+    #[cfg(feature = "cache")]
+    pub fn take_cache(&mut self) -> Option<Cache> {
+        self.cache.take()
+    }
+
+    /// Returns true if the cache loaded in was completely correct.
+    #[cfg(feature = "cache")]
+    pub fn cache_was_correct(&self) -> bool {
+        self.cache_report
+            .as_ref()
+            .is_some_and(CacheReport::is_correct)
+    }
+
+    /// Describes how the loaded cache was used, if caching was enabled.
+    #[cfg(feature = "cache")]
+    pub fn cache_report(&self) -> Option<&CacheReport> {
+        self.cache_report.as_ref()
+    }
 }
 
-/// Provides an impl of the `Init` trait for a type.
+/// A tree with only its earlier phases initialized, created with `InitTree::init_phase()`.
+///
+/// Any structures still in the tree when it's dropped are dropped in the reverse of the order
+/// they were initialized in.
+pub struct PartialTree {
+    tree: InitTree,
+    initialized: internal::Instances<'static>,
+    phases: HashMap<TypeId, u32>,
+}
+
+impl PartialTree {
+    /// Borrows an initialized structure, such as a logger set up in an early phase.
+    pub fn get<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        let value = self.initialized.get(&TypeId::of::<T>())?;
+        RefMut::filter_map(value.borrow_mut(), |v| v.downcast_mut::<T>()).ok()
+    }
+
+    /// Initializes the types in `phase` or any earlier phase that haven't been initialized yet.
+    pub fn init_phase(mut self, phase: u32) -> Result<PartialTree, InitError> {
+        self.init_until(phase)?;
+        Ok(self)
+    }
+
+    /// Initializes every remaining type, returning the fully initialized tree.
+    pub fn finish(mut self) -> Result<InitializedTree, InitError> {
+        self.init_until(u32::MAX)?;
+        self.initialized.resolve_late()?;
+        Ok(self.tree.into_initialized(self.initialized))
+    }
+
+    fn init_until(&mut self, phase: u32) -> Result<(), InitError> {
+        let tree = &mut self.tree;
+        let phases = &self.phases;
+        let (now, later) = tree
+            .uninitialized
+            .drain(..)
+            .partition::<Vec<_>, _>(|d| phases[&(d.id)()] <= phase);
+        tree.uninitialized = now;
+        let mut result = tree.init_ready(&mut self.initialized, || true).map(|_| ());
+        if result.is_ok() && !tree.uninitialized.is_empty() {
+            result = Err(tree.unresolved(&self.initialized));
+        }
+        tree.uninitialized.extend(later);
+        result
+    }
+}
+
+/// A tree being initialized a few types at a time, created with `InitTree::init_incremental()`.
+pub struct IncrementalInit {
+    tree: InitTree,
+    initialized: internal::Instances<'static>,
+    total: usize,
+    #[cfg(feature = "cache")]
+    fingerprint: u64,
+    /// The order given by the loaded cache.
+    #[cfg(feature = "cache")]
+    cached: Vec<String>,
+    /// How much of the cached order has been replayed.
+    #[cfg(feature = "cache")]
+    replay_position: usize,
+    /// The number of types initialized from the cache, once all of it has been replayed.
+    #[cfg(feature = "cache")]
+    replayed: Option<usize>,
+    #[cfg(feature = "cache")]
+    report: CacheReport,
+}
+
+/// How far along an `IncrementalInit` is, returned by `IncrementalInit::step()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of types initialized so far.
+    pub done: usize,
+    /// The number of types to initialize in total.
+    pub total: usize,
+    /// The type initialized most recently, if any.
+    pub current: Option<NodeInfo>,
+}
+
+impl Progress {
+    /// Returns true once every type has been initialized.
+    pub fn is_finished(&self) -> bool {
+        self.done == self.total
+    }
+}
+
+impl IncrementalInit {
+    /// Initializes types until `budget` is spent, returning how far along initialization is.
+    /// At least one type is initialized per step if any are left, so a type taking longer than
+    /// the budget still gets initialized.
+    ///
+    /// If initialization was cancelled, everything initialized so far is dropped in the reverse
+    /// of the order it was initialized in before `InitError::Cancelled` is returned.
+    pub fn step(&mut self, budget: Duration) -> Result<Progress, InitError> {
+        let result = self.advance(budget);
+        if let Err(InitError::Cancelled { .. }) = result {
+            self.initialized = internal::Instances::new(None);
+        }
+        result
+    }
+
+    fn advance(&mut self, budget: Duration) -> Result<Progress, InitError> {
+        let start = Instant::now();
+        let mut stepped = false;
+        #[cfg(feature = "cache")]
+        {
+            let cached = &self.cached;
+            let position = &mut self.replay_position;
+            let report = &mut self.report;
+            let order = std::iter::from_fn(|| {
+                if stepped && start.elapsed() >= budget {
+                    return None;
+                }
+                let name = cached.get(*position)?;
+                *position += 1;
+                stepped = true;
+                Some(name.as_str())
+            });
+            self.tree
+                .replay(order, true, &mut self.initialized, |name, outcome| {
+                    report.record(name, outcome)
+                })?;
+            if self.replay_position < self.cached.len() {
+                return Ok(self.progress());
+            }
+            self.replayed.get_or_insert(self.tree.built.len());
+        }
+        let ran_out = self.tree.init_ready(&mut self.initialized, || {
+            let keep_going = !(stepped && start.elapsed() >= budget);
+            stepped = true;
+            keep_going
+        })?;
+        if ran_out && !self.tree.uninitialized.is_empty() {
+            return Err(self.tree.unresolved(&self.initialized));
+        }
+        Ok(self.progress())
+    }
+
+    /// Initializes every remaining type without a budget, returning the fully initialized
+    /// tree.
+    pub fn finish(mut self) -> Result<InitializedTree, InitError> {
+        while !self.step(Duration::MAX)?.is_finished() {}
+        self.initialized.resolve_late()?;
+        #[cfg(feature = "cache")]
+        self.tree.store_cache(
+            self.fingerprint,
+            self.replayed.unwrap_or_default(),
+            self.report,
+        );
+        Ok(self.tree.into_initialized(self.initialized))
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            done: self.total - self.tree.uninitialized.len(),
+            total: self.total,
+            current: self.tree.built.last().map(NodeInfo::from),
+        }
+    }
+}
+
+/// Structures initialized in a child scope of an `InitializedTree`, created with
+/// `InitializedTree::scope()`.
+///
+/// Any structures still in the scope when it's dropped are dropped in the reverse of the order
+/// they were initialized in.
+pub struct ScopedTree<'p> {
+    initialized: internal::Instances<'p>,
+}
+
+impl<'p> ScopedTree<'p> {
+    /// Removes the initialized structure from this scope and returns it. Structures belonging to
+    /// a parent scope can't be taken from here.
+    pub fn take<T: 'static>(&mut self) -> Option<T> {
+        self.initialized
+            .remove(&TypeId::of::<T>())
+            .map(|v| *v.downcast::<T>().unwrap())
+    }
+
+    /// Removes the initialized structure from this scope and returns it. Prefer `take()` if
+    /// possible, but this function is provided in case the type can't be determined at compile
+    /// time.
+    pub fn take_by_type_id(&mut self, t: TypeId) -> Option<Box<dyn Any>> {
+        self.initialized.remove(&t)
+    }
+
+    /// Initializes the types in `tree` as a child scope of this scope. Types in the new scope may
+    /// depend on structures from this scope or any of its parents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scope can't be initialized. Use `try_scope()` to handle this instead.
+    pub fn scope(&self, tree: InitTree) -> ScopedTree<'_> {
+        self.try_scope(tree).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Initializes the types in `tree` as a child scope of this scope like `scope()`, or returns
+    /// an error if some of them couldn't be initialized.
+    pub fn try_scope(&self, mut tree: InitTree) -> Result<ScopedTree<'_>, InitError> {
+        let initialized = tree.init_within(Some(&self.initialized), None)?;
+        Ok(ScopedTree { initialized })
+    }
+}
+
+/// Constructs fresh instances of a transient type. Request one in `impl_init!` with an argument
+/// such as `sessions: Factory<Session>`, then call `make()` as many times as needed while
+/// initializing.
 ///
-/// This is structured roughly as a function definition. The only acceptable args for it are
-/// mutable references to other structures with an `Init` or `Default` implementation.
+/// A factory borrows the dependencies of T each time it's called, so T must not depend on
+/// anything the caller is itself borrowing.
 ///
 /// # Example
 ///
 /// ```
-/// # use init_tree::impl_init;
-///#[derive(Default, PartialEq, Eq, Debug)]
-/// struct InitDependency;
+/// # use init_tree::{impl_init, Factory, InitTree};
+/// #[derive(Default)]
+/// struct Connection;
 ///
-/// #[derive(PartialEq, Eq, Debug)]
-/// struct InitMe;
+/// struct Pool(Vec<Connection>);
 ///
-/// impl_init!(InitMe; (_dep: &mut InitDependency) {
-///     InitMe
+/// impl_init!(Pool; (connections: Factory<Connection>) {
+///     Pool((0..4).map(|_| connections.make()).collect())
 /// });
+///
+/// let mut tree = InitTree::new();
+/// tree.add::<Pool>();
+/// let mut initialized = tree.init();
+/// assert_eq!(initialized.take::<Pool>().unwrap().0.len(), 4);
+/// assert!(initialized.take::<Connection>().is_none());
 /// ```
-#[macro_export]
-macro_rules! impl_init {
-    ($t:ty; ($($arg:ident: &mut $arg_type:ty),*) $init:block) => {
-        impl $crate::Init for $t
-        {
-            fn init(initialized: &mut std::collections::HashMap<std::any::TypeId, std::cell::RefCell<Box<dyn std::any::Any>>>) -> Option<Self> {
-                $(
-                    let mut $arg = initialized.get(&std::any::TypeId::of::<$arg_type>())?.borrow_mut();
-                    let $arg = $arg.downcast_mut::<$arg_type>().unwrap();
-                )*
-                Some($init)
-            }
+pub struct Factory<'a, T> {
+    initialized: &'a internal::Instances<'a>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Init> Factory<'a, T> {
+    /// Here for use in macros. Creates a factory constructing T from these instances.
+    #[doc(hidden)]
+    pub fn new(initialized: &'a internal::Instances<'a>) -> Self {
+        Self {
+            initialized,
+            marker: PhantomData,
+        }
+    }
+
+    /// Constructs a new instance of T.
+    pub fn make(&self) -> T {
+        T::init(self.initialized).unwrap_or_else(|| {
+            panic!(
+                "Unable to construct transient {}, its dependencies are unavailable",
+                (T::self_def().name)
+            )
+        })
+    }
+}
+
+/// A handle to a structure that's filled in once that structure has been initialized. Request one
+/// in `impl_init!` with an argument such as `bus: Late<EventBus>`.
+///
+/// Unlike a borrow, a `Late` argument doesn't require its structure to be initialized first, so
+/// two structures may legitimately refer to each other as long as one side of the cycle uses
+/// `Late`. Once everything else has been initialized the handle is filled with a clone of the
+/// structure, so T must implement `Clone` and is usually a cheap handle type, such as one
+/// wrapping an `Rc`. Changes made to the structure in the tree after that aren't seen through the
+/// handle unless T shares its state. Initialization fails if any `Late` can't be filled in.
+///
+/// # Example
+///
+/// ```
+/// # use init_tree::{impl_init, InitTree, Late};
+/// struct EventBus {
+///     logger: Late<Logger>,
+/// }
+///
+/// impl_init!(EventBus; (logger: Late<Logger>) {
+///     EventBus { logger }
+/// });
+///
+/// #[derive(Clone)]
+/// struct Logger(u32);
+///
+/// impl_init!(Logger; (_bus: &mut EventBus) {
+///     Logger(7)
+/// });
+///
+/// let mut tree = InitTree::new();
+/// tree.add::<Logger>();
+/// let mut initialized = tree.init();
+/// let bus = initialized.take::<EventBus>().unwrap();
+/// assert_eq!(bus.logger.get().map(|l| l.0), Some(7));
+/// ```
+pub struct Late<T> {
+    slot: Rc<OnceCell<T>>,
+}
+
+impl<T> Late<T> {
+    /// Returns the structure, or `None` if initialization hasn't finished filling this in yet.
+    pub fn get(&self) -> Option<&T> {
+        self.slot.get()
+    }
+}
+
+impl<T> Clone for Late<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Late<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Late")
+            .field("resolved", &self.slot.get().is_some())
+            .finish()
+    }
+}
+
+/// The trait that must be implemented for a type before it can be used with the `InitTree`.
+/// Automatically implemented for `Default` types.
+///
+/// You are discouraged from implementing this manually, and should use the `impl_init` macro
+/// instead.
+pub trait Init: Sized {
+    fn init(initialized: &internal::Instances) -> Option<Self>;
+    fn self_def() -> internal::TypeInitDef;
+    fn deps_list() -> &'static [internal::Dependency];
+    fn deep_deps_list(t: &mut Vec<internal::TypeInitDef>, call_depth: u32);
+}
+
+impl<T: 'static + Default> Init for T {
+    fn init(_: &internal::Instances) -> Option<Self> {
+        Some(Default::default())
+    }
+
+    fn self_def() -> internal::TypeInitDef {
+        internal::TypeInitDef {
+            id: TypeId::of::<Self>,
+            deps: Self::deps_list,
+            init: |h| Self::init(h).map(|h| Box::new(h) as Box<dyn Any>),
+            name: std::any::type_name::<Self>(),
+            type_name: std::any::type_name::<Self>(),
+        }
+    }
+
+    fn deps_list() -> &'static [internal::Dependency] {
+        &[]
+    }
+
+    fn deep_deps_list(_t: &mut Vec<internal::TypeInitDef>, _call_depth: u32) {}
+}
+
+/// Provides an impl of the `Init` trait for a type.
+///
+/// This is structured roughly as a function definition. The acceptable args for it are mutable
+/// references to other structures with an `Init` or `Default` implementation, `Factory<T>` for
+/// structures that should be constructed fresh on demand, `Late<T>` for structures that should be
+/// filled in after this one is initialized, and plain values such as `builder: RouterBuilder`.
+///
+/// A structure taken by value is moved into this one rather than kept in the `InitializedTree`.
+/// Only one type may consume a given structure, and it's initialized after everything else
+/// borrowing that structure.
+///
+/// An `after::<T>()` argument initializes T first without passing it in, for types that only need
+/// the side effects of T. T is also dropped after this type.
+///
+/// A structure can't be borrowed or consumed by the same type more than once, including by the
+/// products of its `Factory` arguments. This can't be caught at compile time, so `init()`
+/// reports it as `InitError::AliasedBorrow` before initializing anything.
+///
+/// # Example
+///
+/// ```
+/// # use init_tree::impl_init;
+///#[derive(Default, PartialEq, Eq, Debug)]
+/// struct InitDependency;
+///
+/// #[derive(PartialEq, Eq, Debug)]
+/// struct InitMe;
+///
+/// impl_init!(InitMe; (_dep: &mut InitDependency) {
+///     InitMe
+/// });
+/// ```
+#[macro_export]
+macro_rules! impl_init {
+    (@args $t:ty; $init:block; [$($parsed:tt)*]; after::<$arg_type:ty>() $(, $($rest:tt)*)?) => {
+        $crate::impl_init!(@args $t; $init; [$($parsed)* (After after $arg_type)]; $($($rest)*)?);
+    };
+    (@args $t:ty; $init:block; [$($parsed:tt)*]; $arg:ident: Factory<$arg_type:ty> $(, $($rest:tt)*)?) => {
+        $crate::impl_init!(@args $t; $init; [$($parsed)* (Factory $arg $arg_type)]; $($($rest)*)?);
+    };
+    (@args $t:ty; $init:block; [$($parsed:tt)*]; $arg:ident: Late<$arg_type:ty> $(, $($rest:tt)*)?) => {
+        $crate::impl_init!(@args $t; $init; [$($parsed)* (Late $arg $arg_type)]; $($($rest)*)?);
+    };
+    (@args $t:ty; $init:block; [$($parsed:tt)*]; $arg:ident: &mut $arg_type:ty $(, $($rest:tt)*)?) => {
+        $crate::impl_init!(@args $t; $init; [$($parsed)* (Borrow $arg $arg_type)]; $($($rest)*)?);
+    };
+    (@args $t:ty; $init:block; [$(($kind:ident $arg:ident $arg_type:ty))*]; ) => {
+        impl $crate::Init for $t
+        {
+            #[allow(unused_variables)]
+            fn init(initialized: &$crate::internal::Instances) -> Option<Self> {
+                $(
+                    $crate::impl_init!(@fetch $kind $arg $arg_type; initialized);
+                )*
+                Some($init)
+            }
+
+            fn self_def() -> $crate::internal::TypeInitDef {
+                $crate::internal::TypeInitDef {
+                    id: std::any::TypeId::of::<Self>,
+                    deps: Self::deps_list,
+                    init: |h| Self::init(h).map(|h| Box::new(h) as Box<dyn std::any::Any>),
+                    name: stringify!($t),
+                    type_name: std::any::type_name::<Self>(),
+                }
+            }
+
+            fn deps_list() -> &'static [$crate::internal::Dependency] {
+                const DEPS: &[$crate::internal::Dependency] = &[$(
+                    $crate::internal::Dependency {
+                        def: <$arg_type as $crate::Init>::self_def,
+                        kind: $crate::internal::DependencyKind::$kind,
+                    },
+                )*];
+                DEPS
+            }
+
+            fn deep_deps_list(t: &mut Vec<$crate::internal::TypeInitDef>, call_depth: u32) {
+                $crate::internal::add_deep_deps(Self::deps_list(), t, call_depth);
+            }
+        }
+    };
+    (@fetch Borrow $arg:ident $arg_type:ty; $initialized:ident) => {
+        let mut $arg = $initialized.get(&std::any::TypeId::of::<$arg_type>())?.borrow_mut();
+        let $arg = $arg.downcast_mut::<$arg_type>().unwrap();
+    };
+    (@fetch Factory $arg:ident $arg_type:ty; $initialized:ident) => {
+        if !$initialized.is_ready(&std::any::TypeId::of::<$arg_type>()) {
+            return None;
+        }
+        let $arg = $crate::Factory::<$arg_type>::new($initialized);
+    };
+    (@fetch Consume $arg:ident $arg_type:ty; $initialized:ident) => {
+        let $arg = *$initialized
+            .consume(&std::any::TypeId::of::<$arg_type>())?
+            .downcast::<$arg_type>()
+            .unwrap();
+    };
+    (@fetch Late $arg:ident $arg_type:ty; $initialized:ident) => {
+        let $arg = $initialized.late::<$arg_type>(<Self as $crate::Init>::self_def().name);
+    };
+    (@fetch After $arg:ident $arg_type:ty; $initialized:ident) => {};
+    (@args $t:ty; $init:block; [$($parsed:tt)*]; $arg:ident: $arg_type:ty $(, $($rest:tt)*)?) => {
+        $crate::impl_init!(@args $t; $init; [$($parsed)* (Consume $arg $arg_type)]; $($($rest)*)?);
+    };
+    ($t:ty; ($($args:tt)*) $init:block) => {
+        $crate::impl_init!(@args $t; $init; []; $($args)*);
+    };
+}
+
+/// These items are required to be public for macros, but their direct use is discouraged.
+pub mod internal {
+    use std::{
+        any::{Any, TypeId},
+        cell::{OnceCell, RefCell},
+        collections::{HashMap, HashSet, VecDeque},
+        rc::Rc,
+    };
+
+    use itertools::join;
+
+    use crate::{Init, InitError, Late};
+
+    /// If your dependency tree goes beyond this many layers deep we'll refuse to initialize it.
+    pub const MAX_TREE_DEPTH: u32 = 500;
+
+    /// Constructs a type from the instances it depends on, returning it in a type erased `Box`.
+    pub type InitFn = fn(&Instances) -> Option<Box<dyn Any>>;
+
+    /// How a type makes use of one of its dependencies.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum DependencyKind {
+        /// A mutable borrow of the dependency's singleton instance.
+        Borrow,
+        /// A `Factory` constructing fresh instances of the dependency.
+        Factory,
+        /// A `Late` handle filled in once the dependency is initialized. Doesn't require the
+        /// dependency to be initialized first.
+        Late,
+        /// The dependency's instance is moved into the dependent.
+        Consume,
+        /// The dependency is initialized first, but isn't passed to the dependent.
+        After,
+    }
+
+    /// A single dependency of a type.
+    #[derive(Clone, Copy)]
+    pub struct Dependency {
+        pub def: fn() -> TypeInitDef,
+        pub kind: DependencyKind,
+    }
+
+    impl Dependency {
+        /// Returns true if the dependency must be ready before the dependent is initialized.
+        pub fn is_ordering(&self) -> bool {
+            self.kind != DependencyKind::Late
+        }
+    }
+
+    /// Returns true if every dependency of this type that must be initialized first is ready.
+    pub(crate) fn deps_ready(def: &TypeInitDef, initialized: &Instances) -> bool {
+        (def.deps)()
+            .iter()
+            .filter(|d| d.is_ordering())
+            .all(|d| initialized.is_ready(&((d.def)().id)()))
+    }
+
+    /// Here for use in macros. Adds these dependencies, and all of their dependencies, to `t`.
+    /// Each type's dependencies are only visited once, so circular dependencies are permitted
+    /// here and reported when the tree is initialized instead. Types are added level by level,
+    /// each level in the order its dependencies are declared.
+    pub fn add_deep_deps(deps: &'static [Dependency], t: &mut Vec<TypeInitDef>, call_depth: u32) {
+        let mut visited = HashSet::new();
+        let mut pending = VecDeque::from(vec![(deps, call_depth)]);
+        while let Some((deps, call_depth)) = pending.pop_front() {
+            if call_depth >= MAX_TREE_DEPTH {
+                panic!(
+                    "Dependency tree too deep. Current tree: [{}]",
+                    get_type_names(t.iter())
+                );
+            }
+            for dep in deps {
+                let def = (dep.def)();
+                if visited.insert((def.id)()) {
+                    t.push(def);
+                    pending.push_back(((def.deps)(), call_depth + 1));
+                }
+            }
+        }
+    }
+
+    /// A `Late` handle waiting to be filled in.
+    struct PendingLate {
+        node: &'static str,
+        dependency: &'static str,
+        fill: Box<dyn FnOnce(&Instances) -> bool>,
+    }
+
+    /// The structures initialized so far, along with a link to the structures of a parent scope,
+    /// if any. Lookups fall back to the parent scope when a type isn't found in this one.
+    ///
+    /// Structures still held when this is dropped are dropped in the reverse of the order they
+    /// were inserted in.
+    #[derive(Default)]
+    pub struct Instances<'p> {
+        values: HashMap<TypeId, RefCell<Box<dyn Any>>>,
+        order: Vec<TypeId>,
+        transients: HashSet<TypeId>,
+        pending_late: RefCell<Vec<PendingLate>>,
+        consumed: RefCell<Vec<TypeId>>,
+        parent: Option<&'p Instances<'p>>,
+    }
+
+    /// Left in place of a value that has been moved into its consumer.
+    struct Consumed;
+
+    impl<'p> Instances<'p> {
+        pub(crate) fn new(parent: Option<&'p Instances<'p>>) -> Self {
+            Self {
+                values: HashMap::new(),
+                order: Vec::new(),
+                transients: HashSet::new(),
+                pending_late: RefCell::new(Vec::new()),
+                consumed: RefCell::new(Vec::new()),
+                parent,
+            }
+        }
+
+        /// Retrieves the instance of a type from this scope, or the nearest parent scope holding
+        /// one.
+        pub fn get(&self, id: &TypeId) -> Option<&RefCell<Box<dyn Any>>> {
+            self.values
+                .get(id)
+                .or_else(|| self.parent.and_then(|p| p.get(id)))
+        }
+
+        /// Returns true if an instance of this type is available from this scope or a parent.
+        pub fn contains(&self, id: &TypeId) -> bool {
+            self.get(id).is_some()
+        }
+
+        /// Returns true if this type has been initialized in this scope or a parent, either as a
+        /// singleton or as a transient type whose dependencies are all available.
+        pub fn is_ready(&self, id: &TypeId) -> bool {
+            self.values.contains_key(id)
+                || self.transients.contains(id)
+                || self.parent.is_some_and(|p| p.is_ready(id))
+        }
+
+        pub(crate) fn mark_ready(&mut self, id: TypeId) {
+            self.transients.insert(id);
+        }
+
+        /// Here for use in macros. Moves the instance of a type out of this scope. Instances held
+        /// by a parent scope can't be consumed.
+        pub fn consume(&self, id: &TypeId) -> Option<Box<dyn Any>> {
+            let mut slot = self.values.get(id)?.borrow_mut();
+            if slot.is::<Consumed>() {
+                return None;
+            }
+            self.consumed.borrow_mut().push(*id);
+            Some(std::mem::replace(&mut *slot, Box::new(Consumed)))
+        }
+
+        /// Removes the entries left behind by `consume()`.
+        pub(crate) fn remove_consumed(&mut self) {
+            for id in std::mem::take(self.consumed.get_mut()) {
+                self.remove(&id);
+            }
+        }
+
+        /// Here for use in macros. Creates a `Late` handle to T requested by `node`, which is
+        /// filled in by `resolve_late()`.
+        pub fn late<T: 'static + Init + Clone>(&self, node: &'static str) -> Late<T> {
+            let slot = Rc::new(OnceCell::new());
+            let target = slot.clone();
+            self.pending_late.borrow_mut().push(PendingLate {
+                node,
+                dependency: T::self_def().name,
+                fill: Box::new(
+                    move |initialized| match initialized.get(&TypeId::of::<T>()) {
+                        Some(value) => {
+                            let value = value.borrow();
+                            let _ = target.set(value.downcast_ref::<T>().unwrap().clone());
+                            true
+                        }
+                        None => false,
+                    },
+                ),
+            });
+            Late { slot }
+        }
+
+        /// Fills in every `Late` handle created so far.
+        pub(crate) fn resolve_late(&self) -> Result<(), InitError> {
+            let pending = std::mem::take(&mut *self.pending_late.borrow_mut());
+            for late in pending {
+                let (node, dependency) = (late.node, late.dependency);
+                if !(late.fill)(self) {
+                    return Err(InitError::UnresolvedLate { node, dependency });
+                }
+            }
+            Ok(())
+        }
+
+        pub(crate) fn insert(&mut self, id: TypeId, value: Box<dyn Any>) {
+            self.values.insert(id, RefCell::new(value));
+            self.order.push(id);
+        }
+
+        /// Removes this type from the scope, returning its instance if it's a singleton.
+        pub(crate) fn remove(&mut self, id: &TypeId) -> Option<Box<dyn Any>> {
+            self.transients.remove(id);
+            // Its slot in `order` is left behind, and skipped once there's no value for it.
+            let value = self.values.remove(id)?;
+            Some(value.into_inner())
+        }
+
+        /// Takes every instance held by this scope, in the order they were inserted.
+        pub(crate) fn into_values(mut self) -> impl Iterator<Item = (TypeId, Box<dyn Any>)> {
+            let mut values = std::mem::take(&mut self.values);
+            // A type removed and inserted again has several slots, and only its last one counts.
+            let mut taken = std::mem::take(&mut self.order)
+                .into_iter()
+                .rev()
+                .filter_map(|id| values.remove(&id).map(|v| (id, v.into_inner())))
+                .collect::<Vec<_>>();
+            taken.reverse();
+            taken.into_iter()
+        }
+    }
+
+    impl Drop for Instances<'_> {
+        fn drop(&mut self) {
+            while let Some(id) = self.order.pop() {
+                self.values.remove(&id);
+            }
+        }
+    }
+
+    /// Largely an implementation detail. However you may need to create one of these if you're manually
+    /// implementing `Init`.
+    #[derive(Clone, Copy)]
+    pub struct TypeInitDef {
+        pub id: fn() -> TypeId,
+        pub deps: fn() -> &'static [Dependency],
+        pub init: InitFn,
+        pub name: &'static str,
+        /// The full name of the type, which identifies it in caches.
+        pub type_name: &'static str,
+    }
+
+    impl TypeInitDef {
+        /// Creates a new instance of this type.
+        ///
+        /// # Arguments
+        ///
+        /// id: The TypeId for the type this will be able to construct.
+        ///
+        /// deps: A function returning the list of direct dependencies for the constructed type.
+        ///
+        /// init: A function that retrieves the needed dependencies from a HashMap, initializes the
+        /// type, and then returns the instance in a type erased `Box`. May return `None` if not all
+        /// dependencies were available.
+        ///
+        /// name: The name of the type this constructs. This is also used as the full name of the
+        /// type when caching, so it should be unique.
+        pub fn new(
+            id: fn() -> TypeId,
+            deps: fn() -> &'static [Dependency],
+            init: InitFn,
+            name: &'static str,
+        ) -> Self {
+            Self {
+                id,
+                deps,
+                init,
+                name,
+                type_name: name,
+            }
+        }
+
+        /// Returns true if `other` provides the same type in the same way, with the same names
+        /// and dependencies. Function pointers aren't compared, since the same function can
+        /// have several addresses.
+        pub(crate) fn same_provider(&self, other: &TypeInitDef) -> bool {
+            let deps = |def: &TypeInitDef| {
+                (def.deps)()
+                    .iter()
+                    .map(|d| (((d.def)().id)(), d.kind))
+                    .collect::<Vec<_>>()
+            };
+            (self.id)() == (other.id)()
+                && self.name == other.name
+                && self.type_name == other.type_name
+                && deps(self) == deps(other)
+        }
+
+        /// Describes this provider by its name and dependencies, such as
+        /// `Renderer(&mut Config, Factory<Gpu>)`.
+        pub(crate) fn signature(&self) -> String {
+            let deps = (self.deps)().iter().map(|d| {
+                let name = (d.def)().name;
+                match d.kind {
+                    DependencyKind::Borrow => format!("&mut {}", name),
+                    DependencyKind::Factory => format!("Factory<{}>", name),
+                    DependencyKind::Late => format!("Late<{}>", name),
+                    DependencyKind::Consume => name.to_owned(),
+                    DependencyKind::After => format!("after::<{}>()", name),
+                }
+            });
+            format!("{}({})", self.name, join(deps, ", "))
+        }
+    }
+    /// Here for use in macros. Returns a comma separated string of the type names in this iterator.
+    pub fn get_type_names<'a>(defs: impl Iterator<Item = &'a TypeInitDef>) -> String {
+        join(defs.map(|d| d.name), ", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct InitA {
+        b: i32,
+        c: i32,
+        d: i32,
+    }
+
+    impl_init!(InitA; (b: &mut InitB, c: &mut InitC, d: &mut InitD) {
+        InitA {
+            b: b.get_handle(),
+            c: c.get_handle(),
+            d: d.0,
+        }
+    });
+
+    #[derive(Default, PartialEq, Eq, Debug)]
+    struct InitB;
+
+    impl InitB {
+        fn get_handle(&self) -> i32 {
+            5
+        }
+    }
+
+    #[derive(Default, PartialEq, Eq, Debug)]
+    struct InitC;
+
+    impl InitC {
+        fn get_handle(&self) -> i32 {
+            7
+        }
+    }
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct InitD(i32);
+
+    impl_init!(InitD; (e: &mut InitE) {
+        InitD(e.get_handle())
+    });
+
+    #[derive(Default, PartialEq, Eq, Debug)]
+    struct InitE;
+
+    impl InitE {
+        fn get_handle(&self) -> i32 {
+            10
+        }
+    }
+
+    #[test]
+    fn test_basic_init() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let mut vals = tree.init();
+        assert_eq!(vals.take::<InitA>(), Some(InitA { b: 5, c: 7, d: 10 }));
+        assert_eq!(vals.take::<InitB>(), Some(InitB));
+        assert_eq!(vals.take::<InitC>(), Some(InitC));
+        assert_eq!(vals.take::<InitD>(), Some(InitD(10)));
+        assert_eq!(vals.take::<InitE>(), Some(InitE));
+    }
+
+    struct CantInitA;
+
+    impl_init!(CantInitA; (_b: &mut CantInitB) {
+        CantInitA
+    });
+
+    struct CantInitB;
+
+    impl_init!(CantInitB; (_a: &mut CantInitA) {
+        CantInitB
+    });
+
+    #[test]
+    #[should_panic]
+    fn test_panic() {
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        tree.init();
+    }
+
+    #[derive(Default, PartialEq, Eq, Debug)]
+    struct BaseCoreInit;
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct CoreInit;
+
+    impl_init!(CoreInit; (_base: &mut BaseCoreInit) {
+        CoreInit
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct LevelOneInit;
+
+    impl_init!(LevelOneInit; (_core: &mut CoreInit) {
+        LevelOneInit
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct LevelTwoInit;
+
+    impl_init!(LevelTwoInit; (_core: &mut CoreInit, _one: &mut LevelOneInit) {
+        LevelTwoInit
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct LevelThreeInit;
+
+    impl_init!(LevelThreeInit; (_core: &mut CoreInit, _two: &mut LevelTwoInit) {
+        LevelThreeInit
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct LevelFourInit;
+
+    impl_init!(LevelFourInit; (_core: &mut CoreInit, _three: &mut LevelThreeInit) {
+        LevelFourInit
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct SelfDep;
+
+    impl_init!(SelfDep; (_me: &mut SelfDep) {
+        SelfDep
+    });
+
+    fn test_init() -> InitTree {
+        let mut tree = InitTree::new();
+        tree.add::<LevelFourInit>();
+        tree.add::<LevelThreeInit>();
+        tree.add::<LevelTwoInit>();
+        tree.add::<LevelOneInit>();
+        tree
+    }
+
+    #[test]
+    fn test_layers_with_shared_dep() {
+        let mut initialized = test_init().init();
+        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
+        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
+        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
+        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_caching() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let mut initialized = init.init();
+        let cache = initialized.take_cache();
+        assert!(!initialized.cache_was_correct());
+        let mut init = test_init();
+        init.load_cache(cache.unwrap());
+        let mut initialized = init.init();
+        assert!(initialized.take_cache().is_some());
+        assert!(initialized.cache_was_correct());
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_independent_of_registration_order() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let cache = init.init().take_cache().unwrap();
+        let mut init = InitTree::new();
+        init.add::<LevelOneInit>();
+        init.add::<LevelTwoInit>();
+        init.add::<LevelThreeInit>();
+        init.add::<LevelFourInit>();
+        init.load_cache(cache);
+        assert!(init.init().cache_was_correct());
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_rejected_on_graph_change() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let cache = init.init().take_cache().unwrap();
+        let mut init = test_init();
+        init.add::<InitA>();
+        init.load_cache(cache);
+        let mut initialized = init.init();
+        assert!(!initialized.cache_was_correct());
+        assert_eq!(
+            initialized.take::<InitA>(),
+            Some(InitA { b: 5, c: 7, d: 10 })
+        );
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+        let mut init = test_init();
+        init.add::<InitA>();
+        init.load_cache(initialized.take_cache().unwrap());
+        assert!(init.init().cache_was_correct());
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_report() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let mut initialized = init.init();
+        let report = initialized.cache_report().unwrap();
+        assert_eq!(report.rejected, Some(CacheRejection::Empty));
+        assert_eq!(report.discovered.len(), 6);
+        let mut cache = initialized.take_cache().unwrap();
+        if let cache::CacheVersion::V2 { order, .. } = &mut cache.inner {
+            order.reverse();
+            order.push("removed::Type".to_owned());
+        }
+        let mut init = test_init();
+        init.load_cache(cache);
+        let initialized = init.init();
+        let report = initialized.cache_report().unwrap();
+        assert!(!initialized.cache_was_correct());
+        assert_eq!(report.rejected, None);
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.not_ready.len(), 5);
+        assert_eq!(report.skipped, ["removed::Type"]);
+        assert_eq!(report.discovered.len(), 5);
+        assert!(report
+            .to_string()
+            .starts_with("cache applied 1 entries, skipped [removed::Type], not ready ["));
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_report_rejected() {
+        let mut init = test_init();
+        init.load_cache(Cache {
+            inner: cache::CacheVersion::V1(vec![0; 10]),
+        });
+        let initialized = init.init();
+        let report = initialized.cache_report().unwrap();
+        assert_eq!(report.rejected, Some(CacheRejection::OutdatedFormat));
+        assert!(report.applied.is_empty());
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "cache rejected because it uses an outdated format, discovered [{}]",
+                report.discovered.join(", ")
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_bytes_round_trip() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let bytes = init.init().take_cache().unwrap().to_bytes();
+        let mut init = test_init();
+        init.load_cache(Cache::from_bytes(&bytes).unwrap());
+        assert!(init.init().cache_was_correct());
+
+        let v1 = Cache {
+            inner: cache::CacheVersion::V1(vec![3, usize::MAX]),
+        }
+        .to_bytes();
+        match Cache::from_bytes(&v1).unwrap().inner {
+            cache::CacheVersion::V1(indices) => assert_eq!(indices, [3, usize::MAX]),
+            _ => panic!("expected a V1 cache"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_bytes_corrupt() {
+        let mut init = test_init();
+        init.enable_caching(true);
+        let bytes = init.init().take_cache().unwrap().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Cache::from_bytes(&bytes[..len]).is_err());
+        }
+        let mut corrupt = bytes.clone();
+        corrupt[20] ^= 1;
+        assert_eq!(
+            Cache::from_bytes(&corrupt).unwrap_err(),
+            CacheDecodeError::ChecksumMismatch
+        );
+        assert_eq!(
+            Cache::from_bytes(b"{\"inner\":[]}").unwrap_err(),
+            CacheDecodeError::BadMagic
+        );
+        let mut newer = bytes;
+        newer[4] = 200;
+        assert_eq!(
+            Cache::from_bytes(&newer).unwrap_err(),
+            CacheDecodeError::UnsupportedVersion(200)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_cache_file() {
+        let path = std::env::temp_dir().join(format!(
+            "init_tree_test_cache_file_{}.cache",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut init = test_init();
+        init.with_cache_file(&path);
+        assert!(!init.init().cache_was_correct());
+        let written = std::fs::read(&path).unwrap();
+
+        let mut init = test_init();
+        init.with_cache_file(&path);
+        assert!(init.init().cache_was_correct());
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+
+        std::fs::write(&path, &written[..written.len() / 2]).unwrap();
+        let mut init = test_init();
+        init.with_cache_file(&path);
+        let mut initialized = init.init();
+        assert!(!initialized.cache_was_correct());
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    #[allow(clippy::legacy_numeric_constants)]
+    fn test_caching_with_massive_indices() {
+        let mut init = test_init();
+        init.load_cache(Cache { inner: cache::CacheVersion::V1(vec![usize::max_value(); 10])});
+        let mut initialized = init.init();
+        assert!(!initialized.cache_was_correct());
+        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
+        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
+        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
+        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_caching_with_indices_equal_to_len() {
+        let mut init = test_init();
+        init.load_cache(Cache { inner: cache::CacheVersion::V1(vec![6; 10])});
+        let mut initialized = init.init();
+        assert!(!initialized.cache_was_correct());
+        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
+        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
+        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
+        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_self_referential_init() {
+        let mut tree = InitTree::new();
+        tree.add::<SelfDep>();
+        let mut init = tree.init();
+        assert_eq!(init.take::<SelfDep>(), Some(SelfDep));
+    }
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct RequestInit(i32);
+
+    impl_init!(RequestInit; (d: &mut InitD, _one: &mut LevelOneInit) {
+        RequestInit(d.0)
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct SessionInit(i32);
+
+    impl_init!(SessionInit; (request: &mut RequestInit) {
+        SessionInit(request.0 + 1)
+    });
+
+    #[test]
+    fn test_scope_borrows_parent() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let parent = tree.init();
+        let mut request = InitTree::new();
+        request.add::<RequestInit>();
+        let mut scope = parent.scope(request);
+        assert_eq!(scope.take::<RequestInit>(), Some(RequestInit(10)));
+        assert_eq!(scope.take::<LevelOneInit>(), Some(LevelOneInit));
+        assert_eq!(scope.take::<CoreInit>(), Some(CoreInit));
+        assert_eq!(scope.take::<InitD>(), None);
+        assert_eq!(scope.take::<InitE>(), None);
+        drop(scope);
+        let mut parent = parent;
+        assert_eq!(parent.take::<InitD>(), Some(InitD(10)));
+    }
+
+    #[derive(Default)]
+    struct ListenerConfig;
+
+    struct Listener;
+
+    impl_init!(Listener; (_config: ListenerConfig) {
+        Listener
+    });
+
+    #[test]
+    fn test_scope_consumes_parent() {
+        let mut tree = InitTree::new();
+        tree.add::<ListenerConfig>();
+        let parent = tree.init();
+        let mut request = InitTree::new();
+        request.add::<Listener>();
+        assert_eq!(
+            parent.try_scope(request).err(),
+            Some(InitError::ConsumedFromParent {
+                node: "Listener",
+                dependency: std::any::type_name::<ListenerConfig>(),
+            })
+        );
+    }
+
+    #[derive(Default, Clone)]
+    struct Blueprint;
+
+    struct Widget;
+
+    impl_init!(Widget; (_blueprint: Blueprint) {
+        Widget
+    });
+
+    struct Workshop;
+
+    impl_init!(Workshop; (widgets: Factory<Widget>) {
+        widgets.make();
+        widgets.make();
+        Workshop
+    });
+
+    struct Inspector;
+
+    impl_init!(Inspector; (_blueprint: &mut Blueprint) {
+        Inspector
+    });
+
+    struct Inspection;
+
+    impl_init!(Inspection; (inspectors: Factory<Inspector>) {
+        inspectors.make();
+        Inspection
+    });
+
+    struct Assembly;
+
+    impl_init!(Assembly; (_blueprint: Blueprint) {
+        Assembly
+    });
+
+    struct Archive {
+        _blueprint: Late<Blueprint>,
+    }
+
+    impl_init!(Archive; (blueprint: Late<Blueprint>) {
+        Archive { _blueprint: blueprint }
+    });
+
+    #[test]
+    fn test_consumed_while_needed() {
+        let blueprint = std::any::type_name::<Blueprint>();
+        let mut transient = InitTree::new();
+        transient.add::<Workshop>();
+        let mut borrowed = InitTree::new();
+        borrowed.add::<Inspection>();
+        borrowed.add::<Assembly>();
+        let mut late = InitTree::new();
+        late.add::<Archive>();
+        late.add::<Assembly>();
+        for (tree, node) in [
+            (transient, "Widget"),
+            (borrowed, "Inspector"),
+            (late, "Archive"),
+        ] {
+            let error = InitError::ConsumedWhileNeeded {
+                node,
+                dependency: blueprint,
+            };
+            assert_eq!(tree.validate(), Err(vec![error.clone()]));
+            assert_eq!(tree.try_init().err(), Some(error));
+        }
+    }
+
+    #[test]
+    fn test_nested_scopes() {
+        let mut tree = InitTree::new();
+        tree.add::<InitD>();
+        let parent = tree.init();
+        let mut request = InitTree::new();
+        request.add::<RequestInit>();
+        let request = parent.scope(request);
+        let mut session = InitTree::new();
+        session.add::<SessionInit>();
+        let mut session = request.scope(session);
+        assert_eq!(session.take::<SessionInit>(), Some(SessionInit(11)));
+        assert_eq!(session.take::<RequestInit>(), None);
+    }
+
+    /// Adds its name to `DROPS` when it's dropped. Each test runs on its own thread, so tests
+    /// only see their own drops.
+    struct DropRecorder(&'static str);
+
+    impl Drop for DropRecorder {
+        fn drop(&mut self) {
+            DROPS.with(|d| d.borrow_mut().push(self.0));
+        }
+    }
+
+    thread_local! {
+        static DROPS: RefCell<Vec<&'static str>> = Default::default();
+    }
+
+    #[derive(Default)]
+    struct DropParent;
+
+    struct ScopedFirst {
+        _drop: DropRecorder,
+    }
+
+    impl_init!(ScopedFirst; (_parent: &mut DropParent) {
+        ScopedFirst {
+            _drop: DropRecorder("first"),
+        }
+    });
+
+    struct ScopedSecond {
+        _drop: DropRecorder,
+    }
+
+    impl_init!(ScopedSecond; (_first: &mut ScopedFirst) {
+        ScopedSecond {
+            _drop: DropRecorder("second"),
+        }
+    });
+
+    #[test]
+    fn test_scope_teardown_order() {
+        let mut tree = InitTree::new();
+        tree.add::<DropParent>();
+        let parent = tree.init();
+        let mut scoped = InitTree::new();
+        scoped.add::<ScopedSecond>();
+        drop(parent.scope(scoped));
+        assert_eq!(DROPS.with(|d| d.borrow().clone()), vec!["second", "first"]);
+    }
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Connection(i32);
+
+    impl_init!(Connection; (e: &mut InitE) {
+        Connection(e.get_handle())
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Pool(Vec<Connection>);
+
+    impl_init!(Pool; (connections: Factory<Connection>, _b: &mut InitB) {
+        Pool(vec![connections.make(), connections.make()])
+    });
+
+    #[test]
+    fn test_transient_factory() {
+        let mut tree = InitTree::new();
+        tree.add::<Pool>();
+        let mut initialized = tree.init();
+        assert_eq!(
+            initialized.take::<Pool>(),
+            Some(Pool(vec![Connection(10), Connection(10)]))
+        );
+        assert_eq!(initialized.take::<Connection>(), None);
+        assert_eq!(initialized.take::<InitE>(), Some(InitE));
+    }
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Client(Connection, i32);
+
+    impl_init!(Client; (shared: &mut Connection, fresh: Factory<Connection>) {
+        shared.0 += 1;
+        Client(fresh.make(), shared.0)
+    });
+
+    #[test]
+    fn test_transient_also_borrowed() {
+        let mut tree = InitTree::new();
+        tree.add_transient::<Connection>();
+        let mut initialized = tree.clone().init();
+        assert_eq!(initialized.take::<Connection>(), None);
+
+        tree.add::<Client>();
+        let mut initialized = tree.init();
+        assert_eq!(
+            initialized.take::<Client>(),
+            Some(Client(Connection(10), 11))
+        );
+        assert_eq!(initialized.take::<Connection>(), Some(Connection(11)));
+    }
+
+    #[test]
+    fn test_reinit_rebuilds_dependents() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let mut initialized = tree.init();
+        let rebuilt = initialized.reinit::<InitE>().unwrap();
+        let names = rebuilt.iter().map(|n| n.name).collect::<Vec<_>>();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[1..], ["InitD", "InitA"]);
+        assert_eq!(rebuilt[0].id, TypeId::of::<InitE>());
+        assert_eq!(
+            initialized.take::<InitA>(),
+            Some(InitA { b: 5, c: 7, d: 10 })
+        );
+        assert_eq!(initialized.take::<InitB>(), Some(InitB));
+        assert_eq!(initialized.take::<InitE>(), Some(InitE));
+    }
+
+    #[test]
+    fn test_reinit_missing_dependency() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let mut initialized = tree.init();
+        assert_eq!(initialized.take::<InitB>(), Some(InitB));
+        assert_eq!(
+            initialized.reinit::<InitD>(),
+            Err(InitError::Unresolved {
+                locked: vec!["InitA"]
+            })
+        );
+        assert_eq!(initialized.take::<InitD>(), Some(InitD(10)));
+        assert_eq!(initialized.take::<InitA>(), None);
+    }
+
+    struct EventBus {
+        subscriber: Late<Subscriber>,
+    }
+
+    impl_init!(EventBus; (subscriber: Late<Subscriber>) {
+        EventBus { subscriber }
+    });
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Subscriber(i32);
+
+    impl_init!(Subscriber; (_bus: &mut EventBus, e: &mut InitE) {
+        Subscriber(e.get_handle())
+    });
+
+    #[test]
+    fn test_late_breaks_cycle() {
+        let mut tree = InitTree::new();
+        tree.add::<EventBus>();
+        let mut initialized = tree.try_init().unwrap();
+        let bus = initialized.take::<EventBus>().unwrap();
+        assert_eq!(bus.subscriber.get(), Some(&Subscriber(10)));
+        assert_eq!(initialized.take::<Subscriber>(), Some(Subscriber(10)));
+    }
+
+    struct Watcher(Late<Subscriber>);
+
+    impl_init!(Watcher; (subscriber: Late<Subscriber>) {
+        Watcher(subscriber)
+    });
+
+    #[test]
+    fn test_late_filled_then_unresolved_after_take() {
+        let mut tree = InitTree::new();
+        tree.add::<Watcher>();
+        let mut initialized = tree.init();
+        let watcher = initialized.take::<Watcher>().unwrap();
+        assert_eq!(watcher.0.get(), Some(&Subscriber(10)));
+        assert_eq!(initialized.take::<Subscriber>(), Some(Subscriber(10)));
+        assert_eq!(
+            initialized.reinit::<Watcher>(),
+            Err(InitError::UnresolvedLate {
+                node: "Watcher",
+                dependency: "Subscriber"
+            })
+        );
+    }
+
+    #[test]
+    fn test_reinit_late_dependents() {
+        let mut tree = InitTree::new();
+        tree.add::<EventBus>();
+        let mut initialized = tree.init();
+        let rebuilt = initialized.reinit::<InitE>().unwrap();
+        let mut names = rebuilt.iter().map(|n| n.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["EventBus", "Subscriber", "init_tree::tests::InitE"]);
+    }
+
+    #[test]
+    fn test_try_init_unresolved() {
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        match tree.try_init() {
+            Err(InitError::Unresolved { mut locked }) => {
+                locked.sort();
+                assert_eq!(locked, ["CantInitA", "CantInitB"]);
+            }
+            _ => panic!("expected an unresolved tree"),
+        }
+    }
+
+    #[derive(Default, PartialEq, Eq, Debug)]
+    struct RouterBuilder(Vec<&'static str>);
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Router(Vec<&'static str>);
+
+    impl_init!(Router; (builder: RouterBuilder) {
+        Router(builder.0)
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Routes;
+
+    impl_init!(Routes; (builder: &mut RouterBuilder) {
+        builder.0.push("/");
+        Routes
+    });
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct OtherRouter;
+
+    impl_init!(OtherRouter; (_builder: RouterBuilder) {
+        OtherRouter
+    });
+
+    #[test]
+    fn test_consume_after_readers() {
+        let mut tree = InitTree::new();
+        tree.add::<Router>();
+        tree.add::<Routes>();
+        let mut initialized = tree.init();
+        assert_eq!(initialized.take::<Router>(), Some(Router(vec!["/"])));
+        assert_eq!(initialized.take::<Routes>(), Some(Routes));
+        assert_eq!(initialized.take::<RouterBuilder>(), None);
+    }
+
+    #[test]
+    fn test_multiple_consumers() {
+        let mut tree = InitTree::new();
+        tree.add::<OtherRouter>();
+        tree.add::<Router>();
+        match tree.try_init() {
+            Err(InitError::MultipleConsumers {
+                dependency,
+                consumers,
+            }) => {
+                assert!(dependency.ends_with("RouterBuilder"));
+                assert_eq!(consumers, ["OtherRouter", "Router"]);
+            }
+            _ => panic!("expected multiple consumers"),
+        }
+    }
+
+    #[test]
+    fn test_plan_levels() {
+        let plan = test_init().plan().unwrap();
+        let batches = plan
+            .batches()
+            .iter()
+            .map(|b| b.iter().map(|s| s.name.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            batches,
+            [
+                vec!["init_tree::tests::BaseCoreInit"],
+                vec!["CoreInit"],
+                vec!["LevelOneInit"],
+                vec!["LevelTwoInit"],
+                vec!["LevelThreeInit"],
+                vec!["LevelFourInit"],
+            ]
+        );
+        assert_eq!(plan.steps[5].dependencies.len(), 2);
+
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let plan = tree.plan().unwrap();
+        assert_eq!(plan.batches().len(), 3);
+        assert_eq!(plan.batches()[0].len(), 3);
+    }
+
+    #[test]
+    fn test_plan_unresolved() {
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        assert!(matches!(tree.plan(), Err(InitError::Unresolved { .. })));
+    }
+
+    #[test]
+    fn test_init_with_plan() {
+        let plan = test_init().plan().unwrap();
+        let mut initialized = test_init().init_with_plan(&plan).unwrap();
+        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        match tree.init_with_plan(&plan) {
+            Err(InitError::PlanMismatch { planned, .. }) => assert_eq!(planned, plan.fingerprint),
+            _ => panic!("expected a plan mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_plan_mismatch_after_order() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        tree.add::<InitB>();
+        let plan = tree.plan().unwrap();
+        tree.order::<InitB, InitA>();
+        match tree.init_with_plan(&plan) {
+            Err(InitError::PlanMismatch { planned, .. }) => assert_eq!(planned, plan.fingerprint),
+            _ => panic!("expected a plan mismatch"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_plan_has_no_cache_report() {
+        let plan = test_init().plan().unwrap();
+        let mut init = test_init();
+        init.enable_caching(true);
+        let initialized = init.init_with_plan(&plan).unwrap();
+        assert!(initialized.cache_report().is_none());
+        assert!(!initialized.cache_was_correct());
+    }
+
+    struct Aliased;
+
+    impl_init!(Aliased; (_a: &mut InitE, _b: &mut InitE) {
+        Aliased
+    });
+
+    /// Depends on `InitB` without adding it to the tree, and claims to be `InitC`.
+    struct Orphan;
+
+    impl Init for Orphan {
+        fn init(_: &internal::Instances) -> Option<Self> {
+            Some(Orphan)
+        }
+
+        fn self_def() -> internal::TypeInitDef {
+            internal::TypeInitDef::new(
+                TypeId::of::<Self>,
+                Self::deps_list,
+                |h| Self::init(h).map(|h| Box::new(h) as Box<dyn Any>),
+                std::any::type_name::<InitC>(),
+            )
+        }
+
+        fn deps_list() -> &'static [internal::Dependency] {
+            &[internal::Dependency {
+                def: InitB::self_def,
+                kind: internal::DependencyKind::Borrow,
+            }]
+        }
+
+        fn deep_deps_list(_t: &mut Vec<internal::TypeInitDef>, _call_depth: u32) {}
+    }
+
+    /// Has nothing to wait for, but fails to initialize.
+    struct Refuses;
+
+    impl Init for Refuses {
+        fn init(_: &internal::Instances) -> Option<Self> {
+            None
+        }
+
+        fn self_def() -> internal::TypeInitDef {
+            internal::TypeInitDef::new(
+                TypeId::of::<Self>,
+                Self::deps_list,
+                |h| Self::init(h).map(|h| Box::new(h) as Box<dyn Any>),
+                "Refuses",
+            )
+        }
+
+        fn deps_list() -> &'static [internal::Dependency] {
+            &[]
+        }
+
+        fn deep_deps_list(_t: &mut Vec<internal::TypeInitDef>, _call_depth: u32) {}
+    }
+
+    #[test]
+    fn test_failed_init() {
+        let expected = InitError::Unresolved {
+            locked: vec!["Refuses"],
+        };
+        let mut tree = InitTree::new();
+        tree.add::<InitE>();
+        tree.add::<Refuses>();
+        assert_eq!(tree.clone().try_init().err(), Some(expected.clone()));
+        let mut incremental = tree.init_incremental().unwrap();
+        assert_eq!(incremental.step(Duration::MAX).err(), Some(expected));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(test_init().validate(), Ok(()));
+        let mut tree = InitTree::new();
+        tree.add::<SelfDep>();
+        assert_eq!(
+            tree.validate(),
+            Err(vec![InitError::Cycle {
+                nodes: vec!["SelfDep"]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        tree.add::<OtherRouter>();
+        tree.add::<Router>();
+        tree.add::<Aliased>();
+        tree.add::<Orphan>();
+        tree.add::<InitC>();
+        let problems = tree.validate().unwrap_err();
+        assert_eq!(
+            problems,
+            [
+                InitError::AliasedBorrow {
+                    node: "Aliased",
+                    dependency: std::any::type_name::<InitE>(),
+                },
+                InitError::MultipleConsumers {
+                    dependency: std::any::type_name::<RouterBuilder>(),
+                    consumers: vec!["OtherRouter", "Router"],
+                },
+                InitError::DuplicateBinding {
+                    type_name: std::any::type_name::<InitC>(),
+                },
+                InitError::MissingProvider {
+                    node: std::any::type_name::<InitC>(),
+                    dependency: std::any::type_name::<InitB>(),
+                },
+                InitError::Cycle {
+                    nodes: vec!["CantInitA", "CantInitB"],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let options = ExportOptions {
+            roots: true,
+            critical_path: true,
+            ..Default::default()
+        };
+        let dot = test_init().to_dot(&options);
+        assert!(dot.starts_with("digraph init_tree {\n"));
+        // Nodes are sorted by full name.
+        assert!(dot.contains("n1 [label=\"CoreInit\", color=\"#0066cc\", penwidth=2];"));
+        assert!(dot.contains(
+            "n2 [label=\"LevelFourInit\", color=\"#0066cc\", penwidth=2, style=\"bold\"];"
+        ));
+        assert!(dot.contains("n2 -> n4 [color=\"#0066cc\", penwidth=2];"));
+        assert!(dot.contains("n2 -> n1;"));
+        assert_eq!(dot.matches(" -> ").count(), 8);
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        tree.add::<Router>();
+        let error = tree.validate().unwrap_err().remove(0);
+        let options = ExportOptions {
+            cycles: true,
+            failed: error.nodes().into_iter().map(str::to_owned).collect(),
+            ..Default::default()
+        };
+        let mermaid = tree.to_mermaid(&options);
+        assert!(mermaid.starts_with("flowchart TD\n    n0[\"CantInitA\"]\n"));
+        assert!(mermaid.contains("    n0 --> n1\n    n1 --> n0\n    n2 ==> n3\n"));
+        assert!(mermaid.contains("    class n0,n1 failed\n"));
+        assert!(mermaid.contains("    class n0,n1 cycle\n"));
+    }
+
+    #[test]
+    fn test_export_timings() {
+        let mut tree = test_init();
+        tree.record_timings(true);
+        let initialized = tree.init();
+        let dot = initialized.to_dot(&ExportOptions::default());
+        assert_eq!(dot.matches("s\"];").count(), 6);
+        assert!(initialized
+            .to_mermaid(&ExportOptions::default())
+            .contains("CoreInit<br/>"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut tree = InitTree::new();
+        tree.add::<Router>();
+        tree.add::<Routes>();
+        tree.tag::<Router>("web");
+        tree.tag::<Router>("core");
+        let manifest = tree.manifest();
+        assert_eq!(manifest, tree.clone().init().manifest());
+        assert_eq!(
+            manifest.to_json(),
+            r#"{
+  "nodes": [
+    {"type_name": "init_tree::tests::Router", "name": "Router", "lifetime": "singleton", "tags": ["core", "web"]},
+    {"type_name": "init_tree::tests::RouterBuilder", "name": "init_tree::tests::RouterBuilder", "lifetime": "consumed", "tags": []},
+    {"type_name": "init_tree::tests::Routes", "name": "Routes", "lifetime": "singleton", "tags": []}
+  ],
+  "edges": [
+    {"from": "init_tree::tests::Router", "to": "init_tree::tests::RouterBuilder", "kind": "consume"},
+    {"from": "init_tree::tests::Routes", "to": "init_tree::tests::RouterBuilder", "kind": "borrow"}
+  ],
+  "roots": [
+    "init_tree::tests::Router",
+    "init_tree::tests::Routes"
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let mut before = InitTree::new();
+        before.add::<Router>();
+        before.add::<Routes>();
+        let mut after = InitTree::new();
+        after.add::<Router>();
+        after.add_transient::<InitB>();
+        let diff = before.manifest().diff(&after.manifest());
+        assert_eq!(diff.added_nodes, ["init_tree::tests::InitB"]);
+        assert_eq!(diff.removed_nodes, ["init_tree::tests::Routes"]);
+        assert!(diff.added_edges.is_empty());
+        assert_eq!(
+            diff.to_string(),
+            "+ init_tree::tests::InitB\n\
+             - init_tree::tests::Routes\n\
+             - init_tree::tests::Routes -> init_tree::tests::RouterBuilder (borrow)\n"
+        );
+        assert!(after.manifest().diff(&after.manifest()).is_empty());
+        let transient = &after.manifest().nodes[0];
+        assert_eq!(transient.lifetime, Lifetime::Transient);
+    }
+
+    struct SlowLeft;
+
+    impl_init!(SlowLeft; (_b: &mut InitB) {
+        std::thread::sleep(Duration::from_millis(30));
+        SlowLeft
+    });
+
+    struct SlowRight;
+
+    impl_init!(SlowRight; (_c: &mut InitC) {
+        std::thread::sleep(Duration::from_millis(10));
+        SlowRight
+    });
+
+    #[test]
+    fn test_timings() {
+        let mut tree = InitTree::new();
+        tree.add::<SlowLeft>();
+        tree.add::<SlowRight>();
+        assert!(tree.clone().init().critical_path().is_none());
+        tree.record_timings(true);
+        let initialized = tree.init();
+        let timings = initialized.timings();
+        assert_eq!(timings.len(), 4);
+        assert_eq!(timings[0].node.name, "SlowLeft");
+        assert_eq!(timings[1].node.name, "SlowRight");
+        assert!(timings[0].elapsed >= Duration::from_millis(30));
+
+        let critical_path = initialized.critical_path().unwrap();
+        let names = critical_path
+            .nodes
+            .iter()
+            .map(|n| n.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["init_tree::tests::InitB", "SlowLeft"]);
+        assert!(critical_path.elapsed >= Duration::from_millis(30));
+        assert!(critical_path.parallelism > 1.0);
+
+        let trace = initialized.chrome_trace().unwrap();
+        assert!(trace.starts_with("{\"traceEvents\": [\n"));
+        assert!(trace.contains("{\"name\": \"SlowLeft\", \"cat\": \"init\", \"ph\": \"X\""));
+        assert_eq!(trace.matches("\"ph\": \"X\"").count(), 4);
+    }
+
+    #[derive(Default)]
+    struct Recorder(std::sync::Mutex<Vec<String>>);
+
+    impl InitObserver for Recorder {
+        fn on_start(&self, node: NodeInfo) {
+            self.0.lock().unwrap().push(format!("start {}", node.name));
+        }
+
+        fn on_success(&self, node: NodeInfo, _elapsed: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("success {}", node.name));
+        }
+
+        fn on_failure(&self, node: NodeInfo, error: &InitError) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("failure {}: {}", node.name, error));
+        }
+
+        fn on_skipped(&self, node: NodeInfo, blocked_by: &[NodeInfo]) {
+            let blocked_by = blocked_by.iter().map(|n| n.name).collect::<Vec<_>>();
+            self.0.lock().unwrap().push(format!(
+                "skipped {} by {}",
+                node.name,
+                blocked_by.join(", ")
+            ));
+        }
+
+        fn on_cache_hit(&self, node: NodeInfo) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("cache hit {}", node.name));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_provider() {
+        let mut tree = InitTree::new();
+        tree.add::<InitD>();
+        // The same provider behind a different function pointer is merged.
+        let mut same = InitD::self_def();
+        same.id = || TypeId::of::<InitD>();
+        tree.uninitialized.push(same);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.clone().init().take::<InitD>(), Some(InitD(10)));
+
+        let mut other = InitD::self_def();
+        other.deps = || &[];
+        tree.uninitialized.push(other);
+        let error = InitError::DuplicateProvider {
+            node: "InitD",
+            first: "InitD(&mut init_tree::tests::InitE)".to_owned(),
+            second: "InitD()".to_owned(),
+        };
+        assert_eq!(tree.validate(), Err(vec![error.clone()]));
+        assert_eq!(tree.try_init().err(), Some(error));
+    }
+
+    #[test]
+    fn test_phases() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        tree.phase::<InitD>(0);
+        tree.phase::<InitC>(1);
+        tree.observe(recorder.clone());
+        let early = tree.init_phase(0).unwrap();
+        assert_eq!(early.get::<InitD>().unwrap().0, 10);
+        assert!(early.get::<InitC>().is_none());
+        let early = early.init_phase(1).unwrap();
+        assert!(early.get::<InitC>().is_some());
+        assert!(early.get::<InitA>().is_none());
+        let mut initialized = early.finish().unwrap();
+        assert_eq!(
+            initialized.take::<InitA>(),
+            Some(InitA { b: 5, c: 7, d: 10 })
+        );
+        let started = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|e| e.strip_prefix("start "))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            started,
+            [
+                "init_tree::tests::InitE",
+                "InitD",
+                "init_tree::tests::InitC",
+                "init_tree::tests::InitB",
+                "InitA",
+            ]
+        );
+
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        tree.phase::<InitA>(0);
+        tree.phase::<InitB>(1);
+        let error = InitError::PhaseOrder {
+            node: "InitA",
+            phase: 0,
+            dependency: std::any::type_name::<InitB>(),
+            dependency_phase: 1,
+        };
+        assert_eq!(tree.validate(), Err(vec![error.clone()]));
+        assert_eq!(tree.init_phase(0).err(), Some(error));
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        let mut init = tree.clone().init_incremental().unwrap();
+        let mut steps = Vec::new();
+        loop {
+            let progress = init.step(Duration::ZERO).unwrap();
+            steps.push((progress.done, progress.current.unwrap().name));
+            if progress.is_finished() {
+                assert_eq!(progress.total, 5);
+                break;
+            }
+        }
+        assert_eq!(
+            steps,
+            [
+                (1, std::any::type_name::<InitB>()),
+                (2, std::any::type_name::<InitC>()),
+                (3, std::any::type_name::<InitE>()),
+                (4, "InitD"),
+                (5, "InitA"),
+            ]
+        );
+        let mut initialized = init.finish().unwrap();
+        assert_eq!(
+            initialized.take::<InitA>(),
+            Some(InitA { b: 5, c: 7, d: 10 })
+        );
+
+        let mut init = tree.init_incremental().unwrap();
+        assert!(init.step(Duration::MAX).unwrap().is_finished());
+        assert!(init.finish().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_incremental_cache() {
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        tree.enable_caching(true);
+        let cache = tree.clone().init().take_cache().unwrap();
+        tree.load_cache(cache);
+        let mut init = tree.init_incremental().unwrap();
+        let mut steps = 0;
+        while !init.step(Duration::ZERO).unwrap().is_finished() {
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+        let initialized = init.finish().unwrap();
+        assert!(initialized.cache_was_correct());
+        assert_eq!(initialized.cache_report().unwrap().applied.len(), 5);
+    }
+
+    #[test]
+    fn test_registration_order() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<InitA>();
+        tree.observe(recorder.clone());
+        tree.init();
+        let started = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|e| e.strip_prefix("start "))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            started,
+            [
+                "init_tree::tests::InitB",
+                "init_tree::tests::InitC",
+                "init_tree::tests::InitE",
+                "InitD",
+                "InitA",
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct MetricsStore;
+
+    #[derive(Default)]
+    struct AlertRules;
+
+    struct Metrics;
+
+    impl_init!(Metrics; (_store: &mut MetricsStore) {
+        Metrics
+    });
+
+    struct Alerts;
+
+    impl_init!(Alerts; (_rules: &mut AlertRules) {
+        Alerts
+    });
+
+    struct Dashboard;
+
+    impl_init!(Dashboard; (_metrics: &mut Metrics, _alerts: &mut Alerts) {
+        Dashboard
+    });
+
+    #[test]
+    fn test_registration_order_nested() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<Dashboard>();
+        tree.observe(recorder.clone());
+        let plan = tree.plan().unwrap();
+        tree.init();
+        let started = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|e| e.strip_prefix("start "))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            started,
+            [
+                "init_tree::tests::MetricsStore",
+                "Metrics",
+                "init_tree::tests::AlertRules",
+                "Alerts",
+                "Dashboard",
+            ]
+        );
+        let planned = plan.steps.iter().map(|s| &s.name).collect::<Vec<_>>();
+        assert_eq!(planned, started.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_observer() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<InitD>();
+        tree.observe(recorder.clone());
+        let mut initialized = tree.init();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "start init_tree::tests::InitE",
+                "success init_tree::tests::InitE",
+                "start InitD",
+                "success InitD",
+            ]
+        );
+        recorder.0.lock().unwrap().clear();
+        initialized.reinit::<InitD>().unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["start InitD", "success InitD"]
+        );
+    }
+
+    #[test]
+    fn test_observed_tree_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let mut tree = InitTree::new();
+        tree.observe(Arc::new(Recorder::default()));
+        assert_send(&tree);
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn test_observer_cache_hit() {
+        let mut tree = InitTree::new();
+        tree.add::<InitD>();
+        tree.enable_caching(true);
+        let cache = tree.clone().init().take_cache().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        tree.load_cache(cache);
+        tree.observe(recorder.clone());
+        tree.init();
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[2], "cache hit init_tree::tests::InitE");
+        assert_eq!(events[5], "cache hit InitD");
+    }
+
+    #[test]
+    fn test_observer_skipped() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<CantInitA>();
+        tree.add::<InitD>();
+        tree.observe(recorder.clone());
+        assert!(tree.try_init().is_err());
+        let mut events = recorder.0.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(
+            events[..2],
+            [
+                "skipped CantInitA by CantInitB",
+                "skipped CantInitB by CantInitA"
+            ]
+        );
+        assert_eq!(events.len(), 6);
+    }
+
+    /// Collects the fields of every span as `name field=value` strings.
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct SpanRecorder {
+        spans: std::sync::Mutex<Vec<Vec<String>>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl SpanRecorder {
+        fn visit(
+            &self,
+            id: &tracing::span::Id,
+            visit: impl FnOnce(&mut dyn tracing::field::Visit),
+        ) {
+            struct Fields<'a>(&'a mut Vec<String>);
+
+            impl tracing::field::Visit for Fields<'_> {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+                    self.0.push(format!("{}={:?}", field.name(), value));
+                }
 
-            fn self_def() -> $crate::internal::TypeInitDef {
-                $crate::internal::TypeInitDef {
-                    id: std::any::TypeId::of::<Self>,
-                    deps: Self::deps_list,
-                    init: |h| Self::init(h).map(|h| Box::new(h) as Box<dyn std::any::Any>),
-                    name: stringify!($t),
+                fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                    self.0.push(format!("{}={}", field.name(), value));
                 }
             }
 
-            #[allow(non_upper_case_globals)]
-            fn deps_list() -> &'static [$crate::internal::TypeInitDef] {
-                $(const $arg: $crate::internal::TypeInitDef = $crate::internal::TypeInitDef {
-                    id: std::any::TypeId::of::<$arg_type>,
-                    deps: <$arg_type as $crate::Init>::deps_list,
-                    init: |h| <$arg_type as $crate::Init>::init(h).map(|h| Box::new(h) as Box<dyn std::any::Any>),
-                    name: stringify!($arg_type),
-                };)*
-                &[$($arg,)*]
-            }
+            let mut spans = self.spans.lock().unwrap();
+            visit(&mut Fields(&mut spans[id.into_u64() as usize - 1]));
+        }
+    }
 
-            fn deep_deps_list(t: &mut Vec<$crate::internal::TypeInitDef>, call_depth: u32) {
-                if call_depth >= $crate::internal::MAX_TREE_DEPTH {
-                    panic!(
-                        "Dependency tree too deep, this is usually due to a circular dependency. Current tree: [{}]",
-                        $crate::internal::get_type_names(t.iter())
-                    );
-                }
-                t.extend(Self::deps_list().iter());
-                $(
-                    <$arg_type as $crate::Init>::deep_deps_list(t, call_depth + 1);
-                )*
-            }
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata) -> bool {
+            true
         }
-    };
-}
 
-/// These items are required to be public for macros, but their direct use is discouraged.
-pub mod internal {
-    use std::{
-        any::{Any, TypeId},
-        cell::RefCell,
-        collections::HashMap,
-    };
+        fn new_span(&self, span: &tracing::span::Attributes) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(vec![span.metadata().name().to_owned()]);
+            let id = tracing::span::Id::from_u64(spans.len() as u64);
+            drop(spans);
+            self.visit(&id, |v| span.record(v));
+            id
+        }
 
-    use itertools::join;
+        fn record(&self, id: &tracing::span::Id, values: &tracing::span::Record) {
+            self.visit(id, |v| values.record(v));
+        }
 
-    /// If your dependency tree goes beyond this many layers deep we'll refuse to initialize it.
-    pub const MAX_TREE_DEPTH: u32 = 500;
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
 
-    /// Largely an implementation detail. However you may need to create one of these if you're manually
-    /// implementing `Init`.
-    #[derive(Clone, Copy)]
-    pub struct TypeInitDef {
-        pub id: fn() -> TypeId,
-        pub deps: fn() -> &'static [TypeInitDef],
-        pub init: fn(&mut HashMap<TypeId, RefCell<Box<dyn Any>>>) -> Option<Box<dyn Any>>,
-        pub name: &'static str,
+        fn event(&self, _: &tracing::Event) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
     }
 
-    impl TypeInitDef {
-        /// Creates a new instance of this type.
-        ///
-        /// # Arguments
-        ///
-        /// id: The TypeId for the type this will be able to construct.
-        ///
-        /// deps: A function returning the list of direct dependencies for the constructed type.
-        ///
-        /// init: A function that retrieves the needed dependencies from a HashMap, initializes the
-        /// type, and then returns the instance in a type erased `Box`. May return `None` if not all
-        /// dependencies were available.
-        ///
-        /// name: The name of the type this constructs.
-        pub fn new(
-            id: fn() -> TypeId,
-            deps: fn() -> &'static [TypeInitDef],
-            init: fn(&mut HashMap<TypeId, RefCell<Box<dyn Any>>>) -> Option<Box<dyn Any>>,
-            name: &'static str,
-        ) -> Self {
-            Self {
-                id,
-                deps,
-                init,
-                name,
-            }
-        }
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_tracing_spans() {
+        let recorder = Arc::new(SpanRecorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<InitD>();
+        tracing::subscriber::with_default(recorder.clone(), || tree.init());
+        assert_eq!(
+            *recorder.spans.lock().unwrap(),
+            [
+                vec!["init_tree", "nodes=2"],
+                vec![
+                    "init_node",
+                    "node=init_tree::tests::InitE",
+                    "type_name=init_tree::tests::InitE",
+                    "cache=miss",
+                    "dependencies=",
+                    "outcome=success",
+                ],
+                vec![
+                    "init_node",
+                    "node=InitD",
+                    "type_name=init_tree::tests::InitD",
+                    "cache=miss",
+                    "dependencies=init_tree::tests::InitE",
+                    "outcome=success",
+                ],
+            ]
+        );
     }
-    /// Here for use in macros. Returns a comma separated string of the type names in this iterator.
-    pub fn get_type_names<'a>(defs: impl Iterator<Item = &'a TypeInitDef>) -> String {
-        join(defs.map(|d| d.name), ", ")
+
+    struct Disk {
+        _drop: DropRecorder,
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    impl_init!(Disk; () {
+        Disk {
+            _drop: DropRecorder("disk"),
+        }
+    });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct InitA {
-        b: i32,
-        c: i32,
-        d: i32,
+    struct Gpu {
+        _drop: DropRecorder,
+        count: u32,
     }
 
-    impl_init!(InitA; (b: &mut InitB, c: &mut InitC, d: &mut InitD) {
-        InitA {
-            b: b.get_handle(),
-            c: c.get_handle(),
-            d: d.0,
+    impl_init!(Gpu; (_disk: &mut Disk) {
+        Gpu {
+            _drop: DropRecorder("gpu"),
+            count: 0,
         }
     });
 
-    #[derive(Default, PartialEq, Eq, Debug)]
-    struct InitB;
+    struct Boom;
 
-    impl InitB {
-        fn get_handle(&self) -> i32 {
-            5
+    impl_init!(Boom; (gpu: &mut Gpu) {
+        if gpu.count == 0 {
+            panic!("no GPU {}", gpu.count);
         }
-    }
+        Boom
+    });
 
-    #[derive(Default, PartialEq, Eq, Debug)]
-    struct InitC;
+    struct App;
 
-    impl InitC {
-        fn get_handle(&self) -> i32 {
-            7
-        }
+    impl_init!(App; (_boom: &mut Boom) {
+        App
+    });
+
+    #[test]
+    fn test_catch_panics() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<App>();
+        tree.catch_panics(true);
+        tree.observe(recorder.clone());
+        let error = tree.try_init().err().unwrap();
+        assert_eq!(
+            error,
+            InitError::Panicked {
+                node: "Boom",
+                message: "no GPU 0".to_owned(),
+                chain: vec!["App", "Boom"],
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Boom panicked during initialization: no GPU 0 (required by App -> Boom)"
+        );
+        assert_eq!(DROPS.with(|d| d.borrow().clone()), vec!["gpu", "disk"]);
+        assert_eq!(
+            recorder.0.lock().unwrap().last().unwrap(),
+            "failure Boom: Boom panicked during initialization: no GPU 0"
+        );
     }
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct InitD(i32);
+    struct AliasedConsume;
 
-    impl_init!(InitD; (e: &mut InitE) {
-        InitD(e.get_handle())
+    impl_init!(AliasedConsume; (_borrowed: &mut InitE, _fresh: Factory<InitE>, _owned: InitE) {
+        AliasedConsume
     });
 
-    #[derive(Default, PartialEq, Eq, Debug)]
-    struct InitE;
+    #[derive(Default, Clone)]
+    struct Shared;
 
-    impl InitE {
-        fn get_handle(&self) -> i32 {
-            10
-        }
+    struct FreshAndShared;
+
+    impl_init!(FreshAndShared; (_fresh: Factory<Shared>, _shared: &mut Shared) {
+        FreshAndShared
+    });
+
+    struct LateAndShared;
+
+    impl_init!(LateAndShared; (_late: Late<Shared>, _shared: &mut Shared) {
+        LateAndShared
+    });
+
+    struct PooledConnection;
+
+    impl_init!(PooledConnection; (_e: &mut InitE) {
+        PooledConnection
+    });
+
+    struct BusyPool;
+
+    impl_init!(BusyPool; (_e: &mut InitE, connections: Factory<PooledConnection>) {
+        connections.make();
+        BusyPool
+    });
+
+    #[test]
+    fn test_aliased_borrow_through_factory() {
+        let mut tree = InitTree::new();
+        tree.add::<FreshAndShared>();
+        tree.add::<LateAndShared>();
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(tree.try_init().is_ok());
+
+        let mut tree = InitTree::new();
+        tree.add::<BusyPool>();
+        let error = InitError::AliasedBorrow {
+            node: "BusyPool",
+            dependency: std::any::type_name::<InitE>(),
+        };
+        assert_eq!(tree.validate(), Err(vec![error.clone()]));
+        assert_eq!(tree.try_init().err(), Some(error));
     }
 
     #[test]
-    fn test_basic_init() {
+    fn test_aliased_borrow() {
         let mut tree = InitTree::new();
-        tree.add::<InitA>();
-        let mut vals = tree.init();
-        assert_eq!(vals.take::<InitA>(), Some(InitA { b: 5, c: 7, d: 10 }));
-        assert_eq!(vals.take::<InitB>(), Some(InitB));
-        assert_eq!(vals.take::<InitC>(), Some(InitC));
-        assert_eq!(vals.take::<InitD>(), Some(InitD(10)));
-        assert_eq!(vals.take::<InitE>(), Some(InitE));
+        tree.add::<Aliased>();
+        assert_eq!(
+            tree.try_init().err(),
+            Some(InitError::AliasedBorrow {
+                node: "Aliased",
+                dependency: std::any::type_name::<InitE>(),
+            })
+        );
+        let mut tree = InitTree::new();
+        tree.add::<AliasedConsume>();
+        assert_eq!(
+            tree.validate(),
+            Err(vec![InitError::AliasedBorrow {
+                node: "AliasedConsume",
+                dependency: std::any::type_name::<InitE>(),
+            }])
+        );
     }
 
-    struct CantInitA;
+    struct CrashReporter {
+        _drop: DropRecorder,
+    }
 
-    impl_init!(CantInitA; (_b: &mut CantInitB) {
-        CantInitA
+    impl_init!(CrashReporter; () {
+        CrashReporter {
+            _drop: DropRecorder("crash reporter"),
+        }
     });
 
-    struct CantInitB;
+    struct Renderer {
+        _drop: DropRecorder,
+        config: i32,
+    }
 
-    impl_init!(CantInitB; (_a: &mut CantInitA) {
-        CantInitB
+    impl_init!(Renderer; (config: &mut InitB, after::<CrashReporter>()) {
+        Renderer {
+            _drop: DropRecorder("renderer"),
+            config: config.get_handle(),
+        }
     });
 
     #[test]
-    #[should_panic]
-    fn test_panic() {
+    fn test_after() {
         let mut tree = InitTree::new();
-        tree.add::<CantInitA>();
-        tree.init();
+        tree.add::<Renderer>();
+        assert!(tree
+            .manifest()
+            .edges
+            .iter()
+            .any(|e| e.to.ends_with("CrashReporter") && e.kind == EdgeKind::After));
+        assert!(tree
+            .to_dot(&ExportOptions::default())
+            .contains("style=dashed, arrowhead=empty"));
+        let mut initialized = tree.init();
+        assert_eq!(initialized.take::<Renderer>().unwrap().config, 5);
+        drop(initialized);
+        assert_eq!(
+            DROPS.with(|d| d.borrow().clone()),
+            vec!["renderer", "crash reporter"]
+        );
     }
 
-    #[derive(Default, PartialEq, Eq, Debug)]
-    struct BaseCoreInit;
+    #[test]
+    fn test_order() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tree = InitTree::new();
+        tree.add::<InitB>();
+        tree.add::<InitC>();
+        tree.order::<InitC, InitB>();
+        // Orderings on types that aren't in the tree are ignored.
+        tree.order::<InitE, InitC>();
+        tree.observe(recorder.clone());
+        assert!(tree.manifest().edges.contains(&ManifestEdge {
+            from: std::any::type_name::<InitB>().to_owned(),
+            to: std::any::type_name::<InitC>().to_owned(),
+            kind: EdgeKind::After,
+        }));
+        tree.init();
+        assert_eq!(
+            recorder.0.lock().unwrap()[..],
+            [
+                "start init_tree::tests::InitC",
+                "success init_tree::tests::InitC",
+                "start init_tree::tests::InitB",
+                "success init_tree::tests::InitB",
+            ]
+        );
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct CoreInit;
+        let mut tree = InitTree::new();
+        tree.add::<InitB>();
+        tree.add::<InitC>();
+        tree.order::<InitC, InitB>();
+        tree.order::<InitB, InitC>();
+        assert!(matches!(
+            tree.validate().unwrap_err()[..],
+            [InitError::Cycle { .. }]
+        ));
+    }
 
-    impl_init!(CoreInit; (_base: &mut BaseCoreInit) {
-        CoreInit
+    struct Migrations {
+        _drop: DropRecorder,
+    }
+
+    impl_init!(Migrations; (_e: &mut InitE) {
+        Migrations {
+            _drop: DropRecorder("migrations"),
+        }
     });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct LevelOneInit;
+    struct Migrated;
 
-    impl_init!(LevelOneInit; (_core: &mut CoreInit) {
-        LevelOneInit
+    impl_init!(Migrated; (after::<Migrations>()) {
+        Migrated
     });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct LevelTwoInit;
+    #[test]
+    fn test_task() {
+        let mut tree = InitTree::new();
+        tree.add_task::<Migrations>();
+        tree.add::<Migrated>();
+        tree.record_timings(true);
+        let plan = tree.plan().unwrap();
+        assert_eq!(
+            plan.steps
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>(),
+            [std::any::type_name::<InitE>(), "Migrations", "Migrated"]
+        );
+        let manifest = tree.manifest();
+        let migrations = manifest.nodes.iter().find(|n| n.name == "Migrations");
+        assert_eq!(migrations.unwrap().lifetime, Lifetime::Task);
 
-    impl_init!(LevelTwoInit; (_core: &mut CoreInit, _one: &mut LevelOneInit) {
-        LevelTwoInit
+        let mut initialized = tree.init();
+        assert_eq!(DROPS.with(|d| d.borrow().clone()), vec!["migrations"]);
+        let timings = initialized.timings();
+        assert!(timings.iter().any(|t| t.node.name == "Migrations"));
+        assert!(initialized.take::<Migrations>().is_none());
+        assert!(initialized.take::<Migrated>().is_some());
+    }
+
+    struct MigrationLog;
+
+    impl_init!(MigrationLog; (_migrations: &mut Migrations) {
+        MigrationLog
     });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct LevelThreeInit;
+    #[test]
+    fn test_task_dependency() {
+        let error = InitError::TaskDependency {
+            node: "MigrationLog",
+            task: "Migrations",
+        };
+        let mut tree = InitTree::new();
+        tree.add_task::<Migrations>();
+        tree.add::<MigrationLog>();
+        assert_eq!(tree.validate(), Err(vec![error.clone()]));
+        assert_eq!(tree.try_init().err(), Some(error));
+    }
 
-    impl_init!(LevelThreeInit; (_core: &mut CoreInit, _two: &mut LevelTwoInit) {
-        LevelThreeInit
+    struct Journal {
+        _drop: DropRecorder,
+    }
+
+    impl_init!(Journal; () {
+        Journal {
+            _drop: DropRecorder("journal"),
+        }
     });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct LevelFourInit;
+    struct Telemetry {
+        _drop: DropRecorder,
+    }
 
-    impl_init!(LevelFourInit; (_core: &mut CoreInit, _three: &mut LevelThreeInit) {
-        LevelFourInit
+    impl_init!(Telemetry; (_journal: &mut Journal) {
+        Telemetry {
+            _drop: DropRecorder("telemetry"),
+        }
     });
 
-    #[derive(PartialEq, Eq, Debug)]
-    struct SelfDep;
+    struct Batch;
 
-    impl_init!(SelfDep; (_me: &mut SelfDep) {
-        SelfDep
+    impl_init!(Batch; () {
+        Batch
     });
 
-    fn test_init() -> InitTree {
-        let mut tree = InitTree::new();
-        tree.add::<LevelFourInit>();
-        tree.add::<LevelThreeInit>();
-        tree.add::<LevelTwoInit>();
-        tree.add::<LevelOneInit>();
-        tree
-    }
+    struct Uploader;
 
-    #[test]
-    fn test_layers_with_shared_dep() {
-        let mut initialized = test_init().init();
-        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
-        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
-        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
-        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
-        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
-    }
+    impl_init!(Uploader; (_telemetry: &mut Telemetry, _batches: Factory<Batch>) {
+        Uploader
+    });
 
-    #[test]
-    #[cfg(feature = "cache")]
-    fn test_caching() {
-        let mut init = test_init();
-        init.enable_caching(true);
-        let mut initialized = init.init();
-        let cache = initialized.take_cache();
-        assert!(!initialized.cache_was_correct());
-        let mut init = test_init();
-        init.load_cache(cache.unwrap());
-        let mut initialized = init.init();
-        assert!(initialized.take_cache().is_some());
-        assert!(initialized.cache_was_correct());
-    }
+    struct CancelAfter(CancellationToken, &'static str);
 
-    #[test]
-    #[cfg(feature = "cache")]
-    fn test_caching_with_massive_indices() {
-        let mut init = test_init();
-        init.load_cache(Cache { inner: CacheVersion::V1(vec![usize::max_value(); 10])});
-        let mut initialized = init.init();
-        assert!(!initialized.cache_was_correct());
-        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
-        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
-        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
-        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
-        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+    impl InitObserver for CancelAfter {
+        fn on_success(&self, node: NodeInfo, _elapsed: Duration) {
+            if node.name == self.1 {
+                self.0.cancel();
+            }
+        }
     }
 
     #[test]
-    #[cfg(feature = "cache")]
-    fn test_caching_with_indices_equal_to_len() {
-        let mut init = test_init();
-        init.load_cache(Cache { inner: CacheVersion::V1(vec![6; 10])});
-        let mut initialized = init.init();
-        assert!(!initialized.cache_was_correct());
-        assert_eq!(initialized.take::<CoreInit>(), Some(CoreInit));
-        assert_eq!(initialized.take::<LevelOneInit>(), Some(LevelOneInit));
-        assert_eq!(initialized.take::<LevelTwoInit>(), Some(LevelTwoInit));
-        assert_eq!(initialized.take::<LevelThreeInit>(), Some(LevelThreeInit));
-        assert_eq!(initialized.take::<LevelFourInit>(), Some(LevelFourInit));
+    fn test_cancel() {
+        let token = CancellationToken::new();
+        let mut tree = InitTree::new();
+        tree.add::<Uploader>();
+        tree.cancel_with(token.clone());
+        tree.observe(Arc::new(CancelAfter(token, "Telemetry")));
+        assert_eq!(
+            tree.try_init().err(),
+            Some(InitError::Cancelled {
+                built: vec!["Journal", "Telemetry"],
+                unbuilt: vec!["Uploader"],
+            })
+        );
+        assert_eq!(
+            DROPS.with(|d| d.borrow().clone()),
+            vec!["telemetry", "journal"]
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn test_self_referential_init() {
+    fn test_cancel_incremental() {
+        let token = CancellationToken::new();
         let mut tree = InitTree::new();
-        tree.add::<SelfDep>();
-        let mut init = tree.init();
-        assert_eq!(init.take::<SelfDep>(), Some(SelfDep));
+        tree.add::<Uploader>();
+        tree.cancel_with(token.clone());
+        tree.observe(Arc::new(CancelAfter(token, "Telemetry")));
+        let mut init = tree.init_incremental().unwrap();
+        assert!(matches!(
+            init.step(Duration::MAX),
+            Err(InitError::Cancelled { .. })
+        ));
+        assert_eq!(
+            DROPS.with(|d| d.borrow().clone()),
+            vec!["telemetry", "journal"]
+        );
     }
 
     #[test]
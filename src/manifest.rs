@@ -0,0 +1,363 @@
+//! A machine-readable description of the dependency graph of a tree.
+
+use std::{
+    any::TypeId,
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Display, Formatter, Write},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    internal::{DependencyKind, TypeInitDef},
+    Constraints,
+};
+
+/// Every type in a tree and the dependencies between them, produced by `InitTree::manifest()`
+/// or `InitializedTree::manifest()`.
+///
+/// Nodes and edges are sorted by full type name, so manifests of the same graph are always
+/// identical. `to_json()` writes a stable JSON shape with one node or edge per line, which diffs
+/// well in code review, and `diff()` compares two manifests directly. The same shape is produced
+/// with serde when the `serde` feature is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct GraphManifest {
+    pub nodes: Vec<ManifestNode>,
+    pub edges: Vec<ManifestEdge>,
+    /// The full names of the types nothing else in the tree depends on.
+    pub roots: Vec<String>,
+}
+
+/// A single type in a `GraphManifest`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ManifestNode {
+    /// The full name of the type, which identifies it in the manifest.
+    pub type_name: String,
+    /// The name of the type.
+    pub name: String,
+    pub lifetime: Lifetime,
+    /// The tags added with `InitTree::tag()`, sorted.
+    pub tags: Vec<String>,
+}
+
+/// How long the instance of a type lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Lifetime {
+    /// Initialized once and kept in the `InitializedTree`.
+    Singleton,
+    /// Constructed fresh by a `Factory` every time one is needed.
+    Transient,
+    /// Initialized once and moved into the type consuming it.
+    Consumed,
+    /// Initialized once for its side effects, then dropped.
+    Task,
+}
+
+/// A dependency of one type on another in a `GraphManifest`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ManifestEdge {
+    /// The full name of the dependent type.
+    pub from: String,
+    /// The full name of the type it depends on.
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+/// How a type makes use of one of its dependencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EdgeKind {
+    /// A mutable borrow of the dependency's instance.
+    Borrow,
+    /// A `Factory` of the dependency.
+    Factory,
+    /// A `Late` handle to the dependency.
+    Late,
+    /// The dependency's instance is moved into the dependent.
+    Consume,
+    /// The dependency is initialized first, but isn't passed to the dependent.
+    After,
+}
+
+/// The nodes and edges added and removed between two manifests, produced by
+/// `GraphManifest::diff()`. Formatted with `Display` as one `+` or `-` line per change.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    /// The full names of the types only in the newer manifest.
+    pub added_nodes: Vec<String>,
+    /// The full names of the types only in the older manifest.
+    pub removed_nodes: Vec<String>,
+    pub added_edges: Vec<ManifestEdge>,
+    pub removed_edges: Vec<ManifestEdge>,
+}
+
+impl Lifetime {
+    fn as_str(self) -> &'static str {
+        match self {
+            Lifetime::Singleton => "singleton",
+            Lifetime::Transient => "transient",
+            Lifetime::Consumed => "consumed",
+            Lifetime::Task => "task",
+        }
+    }
+}
+
+impl EdgeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EdgeKind::Borrow => "borrow",
+            EdgeKind::Factory => "factory",
+            EdgeKind::Late => "late",
+            EdgeKind::Consume => "consume",
+            EdgeKind::After => "after",
+        }
+    }
+}
+
+impl From<DependencyKind> for EdgeKind {
+    fn from(kind: DependencyKind) -> Self {
+        match kind {
+            DependencyKind::Borrow => EdgeKind::Borrow,
+            DependencyKind::Factory => EdgeKind::Factory,
+            DependencyKind::Late => EdgeKind::Late,
+            DependencyKind::Consume => EdgeKind::Consume,
+            DependencyKind::After => EdgeKind::After,
+        }
+    }
+}
+
+impl GraphManifest {
+    /// Describes these types. Dependencies on types that aren't among them are left out.
+    pub(crate) fn new(
+        defs: &[TypeInitDef],
+        constraints: &Constraints,
+        tags: &HashMap<TypeId, Vec<String>>,
+    ) -> Self {
+        let names = defs
+            .iter()
+            .map(|d| ((d.id)(), d.type_name))
+            .collect::<HashMap<_, _>>();
+        let mut edges = Vec::new();
+        let mut depended_on = HashSet::new();
+        let mut consumed = HashSet::new();
+        for def in defs {
+            for dep in (def.deps)() {
+                let dep_id = ((dep.def)().id)();
+                if let Some(to) = names.get(&dep_id) {
+                    depended_on.insert(dep_id);
+                    if dep.kind == DependencyKind::Consume {
+                        consumed.insert(dep_id);
+                    }
+                    edges.push(ManifestEdge {
+                        from: def.type_name.to_owned(),
+                        to: (*to).to_owned(),
+                        kind: dep.kind.into(),
+                    });
+                }
+            }
+        }
+        for (before, after) in &constraints.order {
+            if let (Some(from), Some(to)) = (names.get(after), names.get(before)) {
+                depended_on.insert(*before);
+                edges.push(ManifestEdge {
+                    from: (*from).to_owned(),
+                    to: (*to).to_owned(),
+                    kind: EdgeKind::After,
+                });
+            }
+        }
+        edges.sort();
+        edges.dedup();
+        let mut nodes = defs
+            .iter()
+            .map(|def| {
+                let id = (def.id)();
+                let lifetime = if constraints.tasks.contains(&id) {
+                    Lifetime::Task
+                } else if consumed.contains(&id) {
+                    Lifetime::Consumed
+                } else if constraints.singletons.contains(&id) {
+                    Lifetime::Singleton
+                } else {
+                    Lifetime::Transient
+                };
+                let mut tags = tags.get(&id).cloned().unwrap_or_default();
+                tags.sort();
+                tags.dedup();
+                ManifestNode {
+                    type_name: def.type_name.to_owned(),
+                    name: def.name.to_owned(),
+                    lifetime,
+                    tags,
+                }
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.type_name.cmp(&b.type_name));
+        nodes.dedup_by(|a, b| a.type_name == b.type_name);
+        let mut roots = defs
+            .iter()
+            .filter(|d| !depended_on.contains(&(d.id)()))
+            .map(|d| d.type_name.to_owned())
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots.dedup();
+        Self {
+            nodes,
+            edges,
+            roots,
+        }
+    }
+
+    /// Writes this manifest as JSON. The shape is stable: keys are always written in the same
+    /// order, with one node, edge or root per line.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use init_tree::{impl_init, InitTree};
+    /// #[derive(Default)]
+    /// struct Config;
+    ///
+    /// struct Renderer;
+    ///
+    /// impl_init!(Renderer; (_config: &mut Config) {
+    ///     Renderer
+    /// });
+    ///
+    /// let mut tree = InitTree::new();
+    /// tree.add::<Renderer>();
+    /// tree.tag::<Renderer>("graphics");
+    /// let json = tree.manifest().to_json();
+    /// assert!(json.contains(r#""name": "Renderer", "lifetime": "singleton", "tags": ["graphics"]}"#));
+    /// ```
+    pub fn to_json(&self) -> String {
+        let nodes = self.nodes.iter().map(|node| {
+            let tags = node.tags.iter().map(|t| json_string(t)).collect::<Vec<_>>();
+            format!(
+                "{{\"type_name\": {}, \"name\": {}, \"lifetime\": \"{}\", \"tags\": [{}]}}",
+                json_string(&node.type_name),
+                json_string(&node.name),
+                node.lifetime.as_str(),
+                tags.join(", ")
+            )
+        });
+        let edges = self.edges.iter().map(|edge| {
+            format!(
+                "{{\"from\": {}, \"to\": {}, \"kind\": \"{}\"}}",
+                json_string(&edge.from),
+                json_string(&edge.to),
+                edge.kind.as_str()
+            )
+        });
+        let roots = self.roots.iter().map(|r| json_string(r));
+        let mut json = String::from("{\n");
+        json_array(&mut json, "nodes", nodes);
+        json.push_str(",\n");
+        json_array(&mut json, "edges", edges);
+        json.push_str(",\n");
+        json_array(&mut json, "roots", roots);
+        json.push_str("\n}\n");
+        json
+    }
+
+    /// Compares this manifest with a newer one, returning the nodes and edges that were added
+    /// and removed. Nodes are identified by their full type name.
+    pub fn diff(&self, newer: &GraphManifest) -> ManifestDiff {
+        let old_nodes = self
+            .nodes
+            .iter()
+            .map(|n| &n.type_name)
+            .collect::<BTreeSet<_>>();
+        let new_nodes = newer
+            .nodes
+            .iter()
+            .map(|n| &n.type_name)
+            .collect::<BTreeSet<_>>();
+        let old_edges = self.edges.iter().collect::<BTreeSet<_>>();
+        let new_edges = newer.edges.iter().collect::<BTreeSet<_>>();
+        ManifestDiff {
+            added_nodes: new_nodes
+                .difference(&old_nodes)
+                .map(|n| (*n).clone())
+                .collect(),
+            removed_nodes: old_nodes
+                .difference(&new_nodes)
+                .map(|n| (*n).clone())
+                .collect(),
+            added_edges: new_edges
+                .difference(&old_edges)
+                .map(|e| (*e).clone())
+                .collect(),
+            removed_edges: old_edges
+                .difference(&new_edges)
+                .map(|e| (*e).clone())
+                .collect(),
+        }
+    }
+}
+
+impl ManifestDiff {
+    /// Returns true if nothing was added or removed.
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+}
+
+impl Display for ManifestDiff {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for node in &self.added_nodes {
+            writeln!(f, "+ {}", node)?;
+        }
+        for node in &self.removed_nodes {
+            writeln!(f, "- {}", node)?;
+        }
+        for edge in &self.added_edges {
+            writeln!(f, "+ {} -> {} ({})", edge.from, edge.to, edge.kind.as_str())?;
+        }
+        for edge in &self.removed_edges {
+            writeln!(f, "- {} -> {} ({})", edge.from, edge.to, edge.kind.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `"key": [...]` with one item per line.
+fn json_array(json: &mut String, key: &str, items: impl Iterator<Item = String>) {
+    let items = items.collect::<Vec<_>>();
+    if items.is_empty() {
+        let _ = write!(json, "  \"{}\": []", key);
+        return;
+    }
+    let _ = write!(json, "  \"{}\": [\n    {}\n  ]", key, items.join(",\n    "));
+}
+
+/// Quotes and escapes a string for JSON.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
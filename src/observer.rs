@@ -0,0 +1,55 @@
+//! Hooks into the progress of initialization.
+
+use std::time::Duration;
+
+use crate::{InitError, NodeInfo};
+
+/// Receives lifecycle events while a tree is initialized, such as for logging, metrics or
+/// progress bars. Registered with `InitTree::observe()`. Every method does nothing by default,
+/// so only the events of interest need to be implemented.
+///
+/// Events are sent from the thread initializing the tree, in the order they happen. Observers
+/// must be `Send + Sync` because the tree holds them behind an `Arc`, and an `InitTree` can be
+/// built on one thread and sent to another to be initialized.
+///
+/// # Example
+///
+/// ```
+/// # use init_tree::{InitObserver, InitTree, NodeInfo};
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// #[derive(Default)]
+/// struct Progress(Mutex<Vec<&'static str>>);
+///
+/// impl InitObserver for Progress {
+///     fn on_success(&self, node: NodeInfo, _elapsed: Duration) {
+///         self.0.lock().unwrap().push(node.name);
+///     }
+/// }
+///
+/// #[derive(Default)]
+/// struct Config;
+///
+/// let progress = Arc::new(Progress::default());
+/// let mut tree = InitTree::new();
+/// tree.add::<Config>();
+/// tree.observe(progress.clone());
+/// tree.init();
+/// assert_eq!(progress.0.lock().unwrap().len(), 1);
+/// ```
+pub trait InitObserver: Send + Sync {
+    /// `node` is about to be initialized.
+    fn on_start(&self, _node: NodeInfo) {}
+
+    /// `node` was initialized, taking `elapsed`.
+    fn on_success(&self, _node: NodeInfo, _elapsed: Duration) {}
+
+    /// `node` started initializing, but failed.
+    fn on_failure(&self, _node: NodeInfo, _error: &InitError) {}
+
+    /// `node` was never initialized, because the types in `blocked_by` never became available.
+    fn on_skipped(&self, _node: NodeInfo, _blocked_by: &[NodeInfo]) {}
+
+    /// `node` was initialized in the position given by the loaded cache. Sent after
+    /// `on_success()`.
+    fn on_cache_hit(&self, _node: NodeInfo) {}
+}
@@ -0,0 +1,60 @@
+//! Initialization schedules computed ahead of time.
+
+use std::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The order a tree will be initialized in, computed by `InitTree::plan()` without constructing
+/// anything.
+///
+/// A plan can be inspected, compared with the plan of another build, stored, and executed later
+/// with `InitTree::init_with_plan()`. Its `Display` output lists each batch on its own line.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct InitPlan {
+    /// A fingerprint of the dependency graph this plan was computed from.
+    pub fingerprint: u64,
+    /// Every type in the tree, in the order they'll be initialized.
+    pub steps: Vec<PlanStep>,
+}
+
+/// A single type in an `InitPlan`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct PlanStep {
+    /// The name of the type.
+    pub name: String,
+    /// The full name of the type.
+    pub type_name: String,
+    /// One more than the highest level of the types this one has to wait for, or 0 if it
+    /// doesn't have to wait for anything.
+    pub level: usize,
+    /// The full names of the types in the plan this one has to wait for.
+    pub dependencies: Vec<String>,
+}
+
+impl InitPlan {
+    /// Groups the steps by level. The types in a batch don't depend on each other, so they
+    /// could be initialized in parallel once every earlier batch is done.
+    pub fn batches(&self) -> Vec<Vec<&PlanStep>> {
+        let mut batches = Vec::<Vec<&PlanStep>>::new();
+        for step in &self.steps {
+            if batches.len() <= step.level {
+                batches.resize_with(step.level + 1, Vec::new);
+            }
+            batches[step.level].push(step);
+        }
+        batches
+    }
+}
+
+impl Display for InitPlan {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (level, batch) in self.batches().iter().enumerate() {
+            let names = batch.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+            writeln!(f, "{}: {}", level, names.join(", "))?;
+        }
+        Ok(())
+    }
+}
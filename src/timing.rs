@@ -0,0 +1,98 @@
+//! Recording how long each type takes to initialize.
+
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{internal::TypeInitDef, manifest::json_string, NodeInfo};
+
+/// How long a single type took to initialize, from `InitializedTree::timings()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeTiming {
+    pub node: NodeInfo,
+    /// When the type started initializing, relative to the start of initialization.
+    pub start: Duration,
+    pub elapsed: Duration,
+}
+
+/// The chain of types that had to be initialized one after another taking the most time, from
+/// `InitializedTree::critical_path()`. No matter how many types are initialized at once,
+/// initialization can't take less time than this chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CriticalPath {
+    /// The types in the chain, each depending on the one before it.
+    pub nodes: Vec<NodeInfo>,
+    /// The time taken by the types in the chain.
+    pub elapsed: Duration,
+    /// The time taken by every type, divided by the time taken by the chain. This is how many
+    /// times faster initialization could be if independent types were initialized in parallel.
+    pub parallelism: f64,
+}
+
+/// The timings recorded so far.
+#[derive(Clone, Debug)]
+pub(crate) struct Timings {
+    /// When initialization started.
+    epoch: Instant,
+    pub(crate) nodes: HashMap<TypeId, NodeTiming>,
+}
+
+impl Timings {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Starts timing from now.
+    pub(crate) fn restart(&mut self) {
+        self.epoch = Instant::now();
+    }
+
+    /// Notes that a type started initializing at `start`, and took `elapsed`.
+    pub(crate) fn record(&mut self, def: &TypeInitDef, start: Instant, elapsed: Duration) {
+        self.nodes.insert(
+            (def.id)(),
+            NodeTiming {
+                node: def.into(),
+                start: start.saturating_duration_since(self.epoch),
+                elapsed,
+            },
+        );
+    }
+
+    /// Returns every timing, slowest first.
+    pub(crate) fn sorted(&self) -> Vec<NodeTiming> {
+        let mut timings = self.nodes.values().copied().collect::<Vec<_>>();
+        timings.sort_by(|a, b| b.elapsed.cmp(&a.elapsed).then(a.node.name.cmp(b.node.name)));
+        timings
+    }
+
+    /// Writes these timings in the Chrome trace event format, as complete events on a single
+    /// thread.
+    pub(crate) fn chrome_trace(&self) -> String {
+        let mut timings = self.nodes.values().collect::<Vec<_>>();
+        timings.sort_by_key(|t| t.start);
+        let events = timings
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"name\": {}, \"cat\": \"init\", \"ph\": \"X\", \"ts\": {}, \"dur\": {}, \"pid\": 1, \"tid\": 1}}",
+                    json_string(t.node.name),
+                    t.start.as_micros(),
+                    t.elapsed.as_micros()
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut trace = String::from("{\"traceEvents\": [");
+        if !events.is_empty() {
+            let _ = write!(trace, "\n  {}\n", events.join(",\n  "));
+        }
+        trace.push_str("], \"displayTimeUnit\": \"ms\"}\n");
+        trace
+    }
+}
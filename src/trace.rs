@@ -0,0 +1,29 @@
+//! Spans emitted with `tracing` when the `tracing` feature is enabled.
+
+use itertools::join;
+use tracing::{field, info_span, Span};
+
+use crate::internal::TypeInitDef;
+
+/// The span covering the initialization of a whole tree.
+pub(crate) fn tree_span(nodes: usize) -> Span {
+    info_span!("init_tree", nodes)
+}
+
+/// The span covering the initialization of a single type. `outcome` is recorded once it's
+/// known.
+pub(crate) fn node_span(def: &TypeInitDef, cached: bool) -> Span {
+    let span = info_span!(
+        "init_node",
+        node = def.name,
+        type_name = def.type_name,
+        dependencies = field::Empty,
+        cache = if cached { "hit" } else { "miss" },
+        outcome = field::Empty,
+    );
+    if !span.is_disabled() {
+        let dependencies = join((def.deps)().iter().map(|d| (d.def)().name), ", ");
+        span.record("dependencies", dependencies.as_str());
+    }
+    span
+}